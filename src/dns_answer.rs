@@ -1,59 +1,59 @@
+use std::fmt;
+
 use crate::{
-    dns_question::DnsQuestion,
-    dns_serde::{DnsDeserialize, DnsSerialize},
-    dns_type::DnsType,
+    dns_error::DnsError,
+    dns_rdata::RData,
+    dns_serde::{bytes_at, DnsDeserialize, DnsSerialize, DnsWriter},
+    dns_type::RecordType,
     label_seq::LabelSeq,
 };
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct DnsAnswer {
     pub name: LabelSeq,
-    pub _type: DnsType,
+    pub rdata: RData,
     pub _class: u16,
     pub ttl: u32,
 }
 
 impl DnsSerialize for DnsAnswer {
-    fn serialize(&self) -> Vec<u8> {
-        let mut a: Vec<u8> = Vec::new();
-        a.extend_from_slice(&self.name.serialize());
-        a.extend_from_slice(&self._type.int_as_bytes());
-        a.extend_from_slice(&self._class.to_be_bytes());
-        a.extend_from_slice(&self.ttl.to_be_bytes());
-        a.extend_from_slice(&self._type.serialize_to_length_and_data());
-        a
+    fn serialize_to(&self, writer: &mut DnsWriter) {
+        self.name.serialize_to(writer);
+        writer.extend(&self.rdata.record_type().int_as_bytes());
+        writer.extend(&self._class.to_be_bytes());
+        writer.extend(&self.ttl.to_be_bytes());
+        self.rdata.serialize_length_and_data_to(writer);
     }
 }
 
 impl DnsDeserialize for DnsAnswer {
-    fn deserialize(data: &[u8]) -> (&[u8], Self) {
-        let (remainder, name) = LabelSeq::deserialize(data);
-        let _class = u16::from_be_bytes(remainder[2..4].try_into().expect("Invalid data"));
-        let ttl = u32::from_be_bytes(remainder[4..8].try_into().expect("Invalid data"));
-        let (remainder, _type) = DnsType::deserialize(
-            remainder[..2].try_into().expect("Invalid data"),
-            remainder[8..].try_into().expect("Invalid data"),
-        );
-        (
+    fn deserialize_in<'a>(msg: &'a [u8], data: &'a [u8]) -> Result<(&'a [u8], Self), DnsError> {
+        let (remainder, name) = LabelSeq::deserialize_in(msg, data)?;
+        let record_type = RecordType::from_bytes(bytes_at(msg, remainder, 0)?);
+        let _class = u16::from_be_bytes(bytes_at(msg, remainder, 2)?);
+        let ttl = u32::from_be_bytes(bytes_at(msg, remainder, 4)?);
+        let (remainder, rdata) = RData::deserialize(msg, record_type, &remainder[8..])?;
+        Ok((
             remainder,
             Self {
                 name,
-                _type,
+                rdata,
                 _class,
                 ttl,
             },
-        )
+        ))
     }
 }
 
-impl From<&DnsQuestion> for DnsAnswer {
-    fn from(value: &DnsQuestion) -> Self {
-        Self {
-            ttl: 0,
-            name: value.name.clone(),
-            _type: value._type.clone(),
-            _class: value._class,
+/// One master file line: `<name> <ttl> <class> <type> <rdata>`.
+impl fmt::Display for DnsAnswer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} ", self.name, self.ttl)?;
+        match self._class {
+            1 => write!(f, "IN")?,
+            class => write!(f, "CLASS{}", class)?,
         }
+        write!(f, " {} {}", self.rdata.record_type(), self.rdata)
     }
 }
 
@@ -61,7 +61,7 @@ impl Default for DnsAnswer {
     fn default() -> Self {
         Self {
             name: LabelSeq::default(),
-            _type: DnsType::default(),
+            rdata: RData::default(),
             _class: 1,
             ttl: 0,
         }
@@ -70,13 +70,15 @@ impl Default for DnsAnswer {
 
 #[cfg(test)]
 mod tests {
+    use std::net::Ipv4Addr;
+
     use super::*;
 
     #[test]
     fn it_serdes() {
         let a = DnsAnswer {
             name: LabelSeq::_new("codecrafters.io"),
-            _type: DnsType::A(8, 8, 8, 8),
+            rdata: RData::A(Ipv4Addr::new(8, 8, 8, 8)),
             ..Default::default()
         };
         let expected_bytes = [
@@ -84,8 +86,31 @@ mod tests {
             1, 0, 0, 0, 0, 0, 4, 8, 8, 8, 8,
         ];
         assert_eq!(a.serialize(), expected_bytes);
-        let (remainder, da) = DnsAnswer::deserialize(&expected_bytes);
+        let (remainder, da) = DnsAnswer::deserialize(&expected_bytes).unwrap();
         assert_eq!(da, a);
         assert_eq!(remainder.len(), 0);
     }
+
+    #[test]
+    fn it_rejects_bad_rdlength() {
+        let bytes = [2, 105, 111, 0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 5, 8, 8, 8, 8, 8];
+        assert_eq!(
+            DnsAnswer::deserialize(&bytes),
+            Err(DnsError::BadRdLength {
+                offset: 12,
+                rdlength: 5
+            })
+        );
+    }
+
+    #[test]
+    fn it_displays_unknown_type_per_rfc_3597() {
+        let a = DnsAnswer {
+            name: LabelSeq::_new("example.com"),
+            rdata: RData::Unknown(65280, vec![0xde, 0xad]),
+            ttl: 300,
+            ..Default::default()
+        };
+        assert_eq!(a.to_string(), "example.com. 300 IN TYPE65280 \\# 2 dead");
+    }
 }
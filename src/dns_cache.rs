@@ -0,0 +1,545 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    dns_answer::DnsAnswer, dns_question::DnsQuestion, dns_rcode::Rcode, dns_rdata::RData,
+    dns_type::RecordType,
+};
+
+/// Number of questions cached when no size is given on the command line.
+pub const DEFAULT_CACHE_SIZE: usize = 10_000;
+
+/// Prefetch fraction used when none is given on the command line.
+pub const DEFAULT_PREFETCH_FRACTION: PrefetchFraction = PrefetchFraction(0.1);
+
+/// How long an expired entry is kept around to answer with when the resolver is
+/// unreachable (RFC 8767 section 5 suggests one to three days).
+const MAX_STALE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// TTL given to stale records (RFC 8767 section 4).
+const STALE_TTL: u32 = 30;
+
+/// The flags of a query that change what the resolver answers: DO asks for DNSSEC records
+/// (RFC 3225) and CD for data that failed validation (RFC 4035 section 3.2.2).
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct DnssecFlags {
+    pub dnssec_ok: bool,
+    pub checking_disabled: bool,
+}
+
+/// Names are case-insensitive, so they are lowercased in the key. NXDOMAIN applies to every
+/// type of a name, so it is cached without a type (RFC 2308 section 5).
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+struct CacheKey {
+    name: String,
+    _type: Option<RecordType>,
+    _class: u16,
+    dnssec: DnssecFlags, // answers to queries with other flags are cached apart
+}
+
+impl CacheKey {
+    fn new(question: &DnsQuestion, dnssec: DnssecFlags) -> Self {
+        Self {
+            name: question.name.to_string().to_lowercase(),
+            _type: Some(question._type),
+            _class: question._class,
+            dnssec,
+        }
+    }
+
+    fn nxdomain(question: &DnsQuestion, dnssec: DnssecFlags) -> Self {
+        Self {
+            _type: None,
+            ..Self::new(question, dnssec)
+        }
+    }
+}
+
+/// Fraction of its TTL an entry can have left before a hit refreshes it in the background,
+/// from 0 to 1.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PrefetchFraction(f64);
+
+impl PrefetchFraction {
+    /// Returns `None` if `fraction` is not between 0 and 1.
+    pub fn new(fraction: f64) -> Option<Self> {
+        (0.0..=1.0).contains(&fraction).then_some(Self(fraction))
+    }
+}
+
+/// A cached answer to a question: its records for a positive answer, or the SOA record
+/// for an NXDOMAIN or NODATA one.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CachedResponse {
+    pub rcode: Rcode,
+    pub answers: Vec<DnsAnswer>,
+    pub authorities: Vec<DnsAnswer>,
+    pub prefetch: bool, // the entry is about to expire and should be refreshed
+}
+
+struct CacheEntry {
+    response: CachedResponse, // with the TTLs the records had when stored
+    stored_at: Instant,
+    expires_at: Instant,
+    last_used: u64,
+    last_hit: Option<Instant>,
+    prefetching: bool, // a refresh has already been asked for
+}
+
+/// Answers to recently asked questions, served until their smallest TTL runs out and kept a
+/// while longer in case the resolver becomes unreachable. Once full, the least recently used
+/// entry makes room for a new one.
+pub struct DnsCache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    lru: BTreeMap<u64, CacheKey>, // <last used, key>, oldest first
+    capacity: usize,
+    prefetch_fraction: PrefetchFraction,
+    uses: u64,
+}
+
+impl DnsCache {
+    pub fn new(capacity: usize, prefetch_fraction: PrefetchFraction) -> Self {
+        Self {
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            capacity,
+            prefetch_fraction,
+            uses: 0,
+        }
+    }
+
+    /// Returns the cached response to `question` asked with the `dnssec` flags, with TTLs
+    /// reduced by the time it has spent in the cache. A cached NXDOMAIN for the name answers
+    /// questions of any type.
+    ///
+    /// The response asks for a prefetch if the entry has less than the prefetch fraction of
+    /// its TTL left and was already hit within that long before, so it is still popular.
+    /// Only the first such hit asks for one.
+    pub fn get(
+        &mut self,
+        question: &DnsQuestion,
+        dnssec: DnssecFlags,
+        now: Instant,
+    ) -> Option<CachedResponse> {
+        self.get_entry(CacheKey::nxdomain(question, dnssec), now)
+            .or_else(|| self.get_entry(CacheKey::new(question, dnssec), now))
+    }
+
+    fn get_entry(&mut self, key: CacheKey, now: Instant) -> Option<CachedResponse> {
+        let entry = self.entries.get_mut(&key)?;
+        if entry.expires_at <= now {
+            if entry.expires_at + MAX_STALE <= now {
+                self.remove(&key);
+            }
+            return None;
+        }
+        let lifetime = entry.expires_at.duration_since(entry.stored_at);
+        let remaining = entry.expires_at.duration_since(now);
+        let window = lifetime.mul_f64(self.prefetch_fraction.0);
+        let hit_recently = matches!(entry.last_hit, Some(last_hit) if now - last_hit < window);
+        let prefetch = hit_recently && !entry.prefetching && remaining < window;
+        entry.last_hit = Some(now);
+        entry.prefetching |= prefetch;
+        let elapsed = now.duration_since(entry.stored_at).as_secs() as u32;
+        let response = CachedResponse {
+            prefetch,
+            ..with_ttls(&entry.response, |ttl| ttl.saturating_sub(elapsed))
+        };
+        self.touch(key);
+        Some(response)
+    }
+
+    /// Returns the expired response to `question`, if it expired recently enough to still
+    /// be used when the resolver can't be reached. Its records all get a short TTL.
+    pub fn get_stale(
+        &mut self,
+        question: &DnsQuestion,
+        dnssec: DnssecFlags,
+        now: Instant,
+    ) -> Option<CachedResponse> {
+        [
+            CacheKey::nxdomain(question, dnssec),
+            CacheKey::new(question, dnssec),
+        ]
+        .into_iter()
+        .find_map(|key| {
+            let entry = self.entries.get(&key)?;
+            if entry.expires_at > now || entry.expires_at + MAX_STALE <= now {
+                return None;
+            }
+            let response = with_ttls(&entry.response, |_| STALE_TTL);
+            self.touch(key);
+            Some(response)
+        })
+    }
+
+    /// Caches the answers to `question` until the smallest of their TTLs runs out. Answers
+    /// with a TTL of zero must not be cached.
+    pub fn insert(
+        &mut self,
+        question: &DnsQuestion,
+        dnssec: DnssecFlags,
+        answers: Vec<DnsAnswer>,
+        now: Instant,
+    ) {
+        let response = CachedResponse {
+            rcode: Rcode::NoError,
+            answers,
+            authorities: Vec::new(),
+            prefetch: false,
+        };
+        self.insert_entry(CacheKey::new(question, dnssec), response, now);
+    }
+
+    /// Caches an NXDOMAIN, or a NODATA if `rcode` is NOERROR, for `question`. It is kept for
+    /// the smaller of the SOA record's TTL and its MINIMUM field (RFC 2308 section 5).
+    pub fn insert_negative(
+        &mut self,
+        question: &DnsQuestion,
+        dnssec: DnssecFlags,
+        rcode: Rcode,
+        soa: &DnsAnswer,
+        now: Instant,
+    ) {
+        let RData::Soa(soa_data) = &soa.rdata else {
+            return;
+        };
+        let key = match rcode {
+            Rcode::NXDomain => CacheKey::nxdomain(question, dnssec),
+            Rcode::NoError => CacheKey::new(question, dnssec),
+            _ => return,
+        };
+        let response = CachedResponse {
+            rcode,
+            answers: Vec::new(),
+            authorities: vec![DnsAnswer {
+                ttl: soa.ttl.min(soa_data.minimum),
+                ..soa.clone()
+            }],
+            prefetch: false,
+        };
+        self.insert_entry(key, response, now);
+    }
+
+    fn insert_entry(&mut self, key: CacheKey, response: CachedResponse, now: Instant) {
+        let Some(min_ttl) = response
+            .answers
+            .iter()
+            .chain(&response.authorities)
+            .map(|record| record.ttl)
+            .min()
+        else {
+            return;
+        };
+        if min_ttl == 0 || self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.lru.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(
+            key.clone(),
+            CacheEntry {
+                response,
+                stored_at: now,
+                expires_at: now + Duration::from_secs(min_ttl.into()),
+                last_used: 0,
+                last_hit: None,
+                prefetching: false,
+            },
+        );
+        self.touch(key);
+    }
+
+    fn touch(&mut self, key: CacheKey) {
+        let Some(entry) = self.entries.get_mut(&key) else {
+            return;
+        };
+        self.uses += 1;
+        self.lru.remove(&entry.last_used);
+        entry.last_used = self.uses;
+        self.lru.insert(self.uses, key);
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.last_used);
+        }
+    }
+}
+
+/// Copies `response` with each record's TTL mapped through `ttl`.
+fn with_ttls(response: &CachedResponse, ttl: impl Fn(u32) -> u32) -> CachedResponse {
+    let map = |records: &Vec<DnsAnswer>| {
+        records
+            .iter()
+            .map(|record| DnsAnswer {
+                ttl: ttl(record.ttl),
+                ..record.clone()
+            })
+            .collect()
+    };
+    CachedResponse {
+        rcode: response.rcode,
+        answers: map(&response.answers),
+        authorities: map(&response.authorities),
+        prefetch: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::{dns_rdata::Soa, label_seq::LabelSeq};
+
+    const PLAIN: DnssecFlags = DnssecFlags {
+        dnssec_ok: false,
+        checking_disabled: false,
+    };
+
+    fn question(name: &str) -> DnsQuestion {
+        DnsQuestion {
+            name: LabelSeq::_new(name),
+            ..Default::default()
+        }
+    }
+
+    fn answer(name: &str, ttl: u32) -> DnsAnswer {
+        DnsAnswer {
+            name: LabelSeq::_new(name),
+            rdata: RData::A(Ipv4Addr::new(8, 8, 8, 8)),
+            ttl,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_serves_hits_with_decremented_ttls() {
+        let mut cache = DnsCache::new(10, DEFAULT_PREFETCH_FRACTION);
+        let now = Instant::now();
+        cache.insert(
+            &question("google.com"),
+            PLAIN,
+            vec![answer("google.com", 60), answer("google.com", 300)],
+            now,
+        );
+        let answers = cache
+            .get(
+                &question("Google.COM"),
+                PLAIN,
+                now + Duration::from_secs(10),
+            )
+            .unwrap()
+            .answers;
+        assert_eq!(answers.iter().map(|a| a.ttl).collect::<Vec<_>>(), [50, 290]);
+        assert_eq!(
+            cache.get(
+                &question("google.com"),
+                PLAIN,
+                now + Duration::from_secs(60)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn it_keeps_types_apart() {
+        let mut cache = DnsCache::new(10, DEFAULT_PREFETCH_FRACTION);
+        let now = Instant::now();
+        cache.insert(
+            &question("google.com"),
+            PLAIN,
+            vec![answer("google.com", 60)],
+            now,
+        );
+        let aaaa = DnsQuestion {
+            _type: RecordType::Aaaa,
+            ..question("google.com")
+        };
+        assert_eq!(cache.get(&aaaa, PLAIN, now), None);
+    }
+
+    #[test]
+    fn it_keeps_dnssec_flags_apart() {
+        let mut cache = DnsCache::new(10, DEFAULT_PREFETCH_FRACTION);
+        let now = Instant::now();
+        let dnssec_ok = DnssecFlags {
+            dnssec_ok: true,
+            ..PLAIN
+        };
+        cache.insert(
+            &question("google.com"),
+            dnssec_ok,
+            vec![answer("google.com", 60)],
+            now,
+        );
+        assert!(cache.get(&question("google.com"), dnssec_ok, now).is_some());
+        assert_eq!(cache.get(&question("google.com"), PLAIN, now), None);
+        let checking_disabled = DnssecFlags {
+            checking_disabled: true,
+            ..dnssec_ok
+        };
+        assert_eq!(
+            cache.get(&question("google.com"), checking_disabled, now),
+            None
+        );
+    }
+
+    #[test]
+    fn it_does_not_cache_zero_ttls() {
+        let mut cache = DnsCache::new(10, DEFAULT_PREFETCH_FRACTION);
+        let now = Instant::now();
+        cache.insert(
+            &question("google.com"),
+            PLAIN,
+            vec![answer("google.com", 0)],
+            now,
+        );
+        assert_eq!(cache.get(&question("google.com"), PLAIN, now), None);
+    }
+
+    #[test]
+    fn it_evicts_least_recently_used() {
+        let mut cache = DnsCache::new(2, DEFAULT_PREFETCH_FRACTION);
+        let now = Instant::now();
+        cache.insert(&question("a.com"), PLAIN, vec![answer("a.com", 60)], now);
+        cache.insert(&question("b.com"), PLAIN, vec![answer("b.com", 60)], now);
+        assert!(cache.get(&question("a.com"), PLAIN, now).is_some());
+        cache.insert(&question("c.com"), PLAIN, vec![answer("c.com", 60)], now);
+        assert!(cache.get(&question("a.com"), PLAIN, now).is_some());
+        assert!(cache.get(&question("b.com"), PLAIN, now).is_none());
+        assert!(cache.get(&question("c.com"), PLAIN, now).is_some());
+    }
+
+    fn soa(ttl: u32, minimum: u32) -> DnsAnswer {
+        DnsAnswer {
+            name: LabelSeq::_new("google.com"),
+            rdata: RData::Soa(Soa {
+                mname: LabelSeq::_new("ns1.google.com"),
+                rname: LabelSeq::_new("dns-admin.google.com"),
+                serial: 1,
+                refresh: 900,
+                retry: 900,
+                expire: 1800,
+                minimum,
+            }),
+            ttl,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_caches_nxdomain_for_every_type() {
+        let mut cache = DnsCache::new(10, DEFAULT_PREFETCH_FRACTION);
+        let now = Instant::now();
+        cache.insert_negative(
+            &question("nope.google.com"),
+            PLAIN,
+            Rcode::NXDomain,
+            &soa(60, 30),
+            now,
+        );
+        let aaaa = DnsQuestion {
+            _type: RecordType::Aaaa,
+            ..question("nope.google.com")
+        };
+        let response = cache
+            .get(&aaaa, PLAIN, now + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(response.rcode, Rcode::NXDomain);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities[0].ttl, 20);
+        assert_eq!(cache.get(&aaaa, PLAIN, now + Duration::from_secs(30)), None);
+    }
+
+    #[test]
+    fn it_caches_nodata_per_type() {
+        let mut cache = DnsCache::new(10, DEFAULT_PREFETCH_FRACTION);
+        let now = Instant::now();
+        let aaaa = DnsQuestion {
+            _type: RecordType::Aaaa,
+            ..question("google.com")
+        };
+        cache.insert_negative(&aaaa, PLAIN, Rcode::NoError, &soa(10, 300), now);
+        let response = cache.get(&aaaa, PLAIN, now).unwrap();
+        assert_eq!(response.rcode, Rcode::NoError);
+        assert_eq!(response.authorities[0].ttl, 10);
+        assert_eq!(cache.get(&question("google.com"), PLAIN, now), None);
+    }
+
+    #[test]
+    fn it_rejects_prefetch_fractions_outside_0_to_1() {
+        for fraction in [-0.1, 1.5, f64::NAN, f64::INFINITY, f64::MAX] {
+            assert_eq!(PrefetchFraction::new(fraction), None);
+        }
+        assert!(PrefetchFraction::new(0.0).is_some());
+        assert!(PrefetchFraction::new(1.0).is_some());
+    }
+
+    #[test]
+    fn it_asks_to_prefetch_popular_entries_near_expiry() {
+        let mut cache = DnsCache::new(10, PrefetchFraction::new(0.1).unwrap());
+        let now = Instant::now();
+        cache.insert(
+            &question("google.com"),
+            PLAIN,
+            vec![answer("google.com", 100)],
+            now,
+        );
+        // a hit long before expiry doesn't make the entry popular near expiry
+        cache.get(&question("google.com"), PLAIN, now).unwrap();
+        let near_expiry = now + Duration::from_secs(92);
+        assert!(
+            !cache
+                .get(&question("google.com"), PLAIN, near_expiry)
+                .unwrap()
+                .prefetch
+        );
+        let near_expiry = near_expiry + Duration::from_secs(3);
+        assert!(
+            cache
+                .get(&question("google.com"), PLAIN, near_expiry)
+                .unwrap()
+                .prefetch
+        );
+        assert!(
+            !cache
+                .get(&question("google.com"), PLAIN, near_expiry)
+                .unwrap()
+                .prefetch
+        );
+    }
+
+    #[test]
+    fn it_serves_stale_entries() {
+        let mut cache = DnsCache::new(10, DEFAULT_PREFETCH_FRACTION);
+        let now = Instant::now();
+        cache.insert(
+            &question("google.com"),
+            PLAIN,
+            vec![answer("google.com", 60)],
+            now,
+        );
+        assert_eq!(cache.get_stale(&question("google.com"), PLAIN, now), None);
+
+        let expired = now + Duration::from_secs(3600);
+        assert_eq!(cache.get(&question("google.com"), PLAIN, expired), None);
+        let stale = cache
+            .get_stale(&question("google.com"), PLAIN, expired)
+            .unwrap();
+        assert_eq!(stale.answers[0].ttl, STALE_TTL);
+
+        let too_old = now + MAX_STALE + Duration::from_secs(60);
+        assert_eq!(
+            cache.get_stale(&question("google.com"), PLAIN, too_old),
+            None
+        );
+    }
+}
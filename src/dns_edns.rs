@@ -0,0 +1,176 @@
+use crate::{
+    dns_answer::DnsAnswer,
+    dns_error::DnsError,
+    dns_rdata::RData,
+    dns_serde::{bytes_at, offset_in, DnsDeserialize, DnsSerialize, DnsWriter},
+    label_seq::LabelSeq,
+};
+
+/// Largest UDP payload we advertise and accept. 1232 bytes avoids IP fragmentation on
+/// common paths (DNS flag day 2020).
+pub const MAX_UDP_PAYLOAD: u16 = 1232;
+
+/// Largest UDP payload every client accepts, and the limit for clients without EDNS
+/// (RFC 1035 section 4.2.1).
+pub const MIN_UDP_PAYLOAD: u16 = 512;
+
+/// The DO (DNSSEC OK) bit of the OPT record's flags (RFC 3225).
+const DNSSEC_OK: u16 = 0x8000;
+
+/// EDNS(0) information carried by the OPT pseudo-record in the additional section (RFC 6891).
+#[derive(Debug, PartialEq, Clone)]
+pub struct Edns {
+    pub udp_payload_size: u16,
+    pub extended_rcode: u8, // upper 8 bits of the 12-bit RCODE
+    pub version: u8,
+    pub flags: u16,
+    pub options: Vec<EdnsOption>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl Edns {
+    /// The OPT record attached to queries forwarded to the resolver.
+    pub fn for_query(dnssec_ok: bool) -> Self {
+        Self {
+            udp_payload_size: MAX_UDP_PAYLOAD,
+            extended_rcode: 0,
+            version: 0,
+            flags: if dnssec_ok { DNSSEC_OK } else { 0 },
+            options: Vec::new(),
+        }
+    }
+
+    /// The OPT record for a response to a query that carried `self`. Options are not echoed
+    /// since none are supported, but the DO bit is.
+    pub fn for_response(&self) -> Self {
+        Self::for_query(self.dnssec_ok())
+    }
+
+    pub fn dnssec_ok(&self) -> bool {
+        self.flags & DNSSEC_OK != 0
+    }
+
+    /// Reads the OPT record's fields, which reuse the CLASS and TTL fields of a regular record.
+    /// Returns `None` if `record` is not an OPT record.
+    pub fn from_record(record: &DnsAnswer) -> Option<Self> {
+        match &record.rdata {
+            RData::Opt(options) => Some(Self {
+                udp_payload_size: record._class,
+                extended_rcode: (record.ttl >> 24) as u8,
+                version: (record.ttl >> 16) as u8,
+                flags: record.ttl as u16,
+                options: options.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_record(&self) -> DnsAnswer {
+        DnsAnswer {
+            name: LabelSeq::default(),
+            rdata: RData::Opt(self.options.clone()),
+            _class: self.udp_payload_size,
+            ttl: (self.extended_rcode as u32) << 24
+                | (self.version as u32) << 16
+                | self.flags as u32,
+        }
+    }
+}
+
+impl DnsSerialize for EdnsOption {
+    fn serialize_to(&self, writer: &mut DnsWriter) {
+        writer.extend(&self.code.to_be_bytes());
+        writer.extend(
+            &u16::try_from(self.data.len())
+                .expect("option length should fit in 2 bytes")
+                .to_be_bytes(),
+        );
+        writer.extend(&self.data);
+    }
+}
+
+impl DnsDeserialize for EdnsOption {
+    fn deserialize_in<'a>(msg: &'a [u8], data: &'a [u8]) -> Result<(&'a [u8], Self), DnsError> {
+        let code = u16::from_be_bytes(bytes_at(msg, data, 0)?);
+        let len = u16::from_be_bytes(bytes_at(msg, data, 2)?) as usize;
+        let option_data = data
+            .get(4..4 + len)
+            .ok_or_else(|| DnsError::UnexpectedEnd {
+                offset: offset_in(msg, data) + 4,
+                needed: 4 + len - data.len(),
+            })?;
+        Ok((
+            &data[4 + len..],
+            Self {
+                code,
+                data: option_data.to_vec(),
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_serdes_opt_record() {
+        // OPT advertising 4096 bytes with DO set and a 8-byte client cookie
+        let bytes = [
+            0, 0, 41, 16, 0, 0, 0, 128, 0, 0, 12, 0, 10, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8,
+        ];
+        let (remainder, record) = DnsAnswer::deserialize(&bytes).unwrap();
+        assert_eq!(remainder.len(), 0);
+        let edns = Edns::from_record(&record).unwrap();
+        assert_eq!(
+            edns,
+            Edns {
+                udp_payload_size: 4096,
+                extended_rcode: 0,
+                version: 0,
+                flags: 0x8000,
+                options: vec![EdnsOption {
+                    code: 10,
+                    data: vec![1, 2, 3, 4, 5, 6, 7, 8]
+                }],
+            }
+        );
+        assert!(edns.dnssec_ok());
+        assert_eq!(edns.to_record().serialize(), bytes);
+    }
+
+    #[test]
+    fn it_builds_response_opt() {
+        let query_edns = Edns {
+            udp_payload_size: 4096,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0x8000,
+            options: vec![EdnsOption {
+                code: 10,
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            }],
+        };
+        let response_edns = query_edns.for_response();
+        assert_eq!(response_edns.udp_payload_size, MAX_UDP_PAYLOAD);
+        assert!(response_edns.dnssec_ok());
+        assert!(response_edns.options.is_empty());
+    }
+
+    #[test]
+    fn it_rejects_truncated_option() {
+        let bytes = [0, 0, 41, 16, 0, 0, 0, 0, 0, 0, 6, 0, 10, 0, 8, 1, 2];
+        assert_eq!(
+            DnsAnswer::deserialize(&bytes),
+            Err(DnsError::UnexpectedEnd {
+                offset: 15,
+                needed: 6
+            })
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use thiserror::Error;
+
+/// Errors produced while parsing DNS wire data. Every variant carries the byte offset
+/// (relative to the start of the message) where the problem was detected.
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+pub enum DnsError {
+    #[error("truncated header: expected 12 bytes but got {len}")]
+    TruncatedHeader { len: usize },
+
+    #[error("unexpected end of data at offset {offset}: needed {needed} more bytes")]
+    UnexpectedEnd { offset: usize, needed: usize },
+
+    #[error("invalid label length {len} at offset {offset}")]
+    BadLabelLength { offset: usize, len: u8 },
+
+    #[error("label at offset {offset} is not valid UTF-8")]
+    InvalidUtf8 { offset: usize },
+
+    #[error("label at offset {offset} contains a dot")]
+    DotInLabel { offset: usize },
+
+    #[error("compression pointer at offset {offset} to {target} does not point backwards")]
+    BadPointer { offset: usize, target: usize },
+
+    #[error("name starting at offset {offset} is longer than 255 bytes")]
+    NameTooLong { offset: usize },
+
+    #[error("invalid RDLENGTH {rdlength} at offset {offset}")]
+    BadRdLength { offset: usize, rdlength: u16 },
+
+    #[error("more than one OPT record in the additional section at offset {offset}")]
+    DuplicateOpt { offset: usize },
+
+    #[error("expected {expected} records at offset {offset} but only found {found}")]
+    CountMismatch {
+        offset: usize,
+        expected: usize,
+        found: usize,
+    },
+}
@@ -1,16 +1,23 @@
-use crate::dns_serde::{DnsDeserialize, DnsSerialize};
+use crate::{
+    dns_error::DnsError,
+    dns_opcode::Opcode,
+    dns_rcode::HeaderRcode,
+    dns_serde::{bytes_at, DnsDeserialize, DnsSerialize, DnsWriter},
+};
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct DnsHeader {
-    pub id: u16,    // packet identifier
-    pub qr: u8,     // 1 bit - query response indicator (1 for reply, 0 for question)
-    pub opcode: u8, // 4 bits
-    pub aa: u8,     // 1 bit
-    pub tc: u8,     // 1 bit
-    pub rd: u8,     // 1 bit
-    pub ra: u8,     // 1 bit
-    pub z: u8,      // 3 bits
-    pub rcode: u8,  // 4 bits
+    pub id: u16,            // packet identifier
+    pub qr: bool,           // query response indicator (true for reply, false for question)
+    pub opcode: Opcode,     // 4 bits
+    pub aa: bool,           // authoritative answer
+    pub tc: bool,           // truncated
+    pub rd: bool,           // recursion desired
+    pub ra: bool,           // recursion available
+    pub z: bool,            // reserved, must be zero
+    pub ad: bool,           // authentic data (RFC 4035 section 3.2.3)
+    pub cd: bool,           // checking disabled (RFC 4035 section 3.2.2)
+    pub rcode: HeaderRcode, // see DnsPacket::rcode for the full RCODE
     pub qdcount: u16,
     pub ancount: u16,
     pub nscount: u16,
@@ -18,65 +25,60 @@ pub struct DnsHeader {
 }
 
 impl DnsSerialize for DnsHeader {
-    fn serialize(&self) -> Vec<u8> {
-        let mut v: Vec<u8> = Vec::with_capacity(12);
-        v.extend_from_slice(&self.id.to_be_bytes());
+    fn serialize_to(&self, writer: &mut DnsWriter) {
+        writer.extend(&self.id.to_be_bytes());
 
         // next byte includes qr, opcode, aa, tc, rd
-        v.push(self.qr << 7 | self.opcode << 3 | self.aa << 2 | self.tc << 1 | self.rd);
+        writer.push(
+            (self.qr as u8) << 7
+                | self.opcode.code() << 3
+                | (self.aa as u8) << 2
+                | (self.tc as u8) << 1
+                | self.rd as u8,
+        );
 
-        // next byte includes ra, z, rcode
-        v.push(self.ra << 7 | self.z << 4 | self.rcode);
+        // next byte includes ra, z, ad, cd, rcode
+        writer.push(
+            (self.ra as u8) << 7
+                | (self.z as u8) << 6
+                | (self.ad as u8) << 5
+                | (self.cd as u8) << 4
+                | self.rcode.bits(),
+        );
 
-        v.extend_from_slice(&self.qdcount.to_be_bytes());
-        v.extend_from_slice(&self.ancount.to_be_bytes());
-        v.extend_from_slice(&self.nscount.to_be_bytes());
-        v.extend_from_slice(&self.arcount.to_be_bytes());
-        v
+        writer.extend(&self.qdcount.to_be_bytes());
+        writer.extend(&self.ancount.to_be_bytes());
+        writer.extend(&self.nscount.to_be_bytes());
+        writer.extend(&self.arcount.to_be_bytes());
     }
 }
 
 impl DnsDeserialize for DnsHeader {
-    fn deserialize(data: &[u8]) -> (&[u8], Self) {
+    fn deserialize_in<'a>(msg: &'a [u8], data: &'a [u8]) -> Result<(&'a [u8], Self), DnsError> {
+        if data.len() < 12 {
+            return Err(DnsError::TruncatedHeader { len: data.len() });
+        }
         let h = Self {
-            id: u16::from_be_bytes(data[..=1].try_into().expect("should have 2 bytes")),
+            id: u16::from_be_bytes(bytes_at(msg, data, 0)?),
 
-            qr: data[2] >> 7 & 0x01,           //1
-            opcode: data[2] >> 3 & 0b00001111, // 4
-            aa: data[2] >> 2 & 0x01,           //1
-            tc: data[2] >> 1 & 0x01,           //1
-            rd: data[2] & 0x01,                //1
+            qr: data[2] & 0x80 != 0,
+            opcode: Opcode::from_bits(data[2] >> 3),
+            aa: data[2] & 0x04 != 0,
+            tc: data[2] & 0x02 != 0,
+            rd: data[2] & 0x01 != 0,
 
-            ra: data[3] >> 7 & 0x01,      // 1
-            z: data[3] >> 4 & 0b00000111, // 3
-            rcode: data[3] & 0b00001111,  // 4
+            ra: data[3] & 0x80 != 0,
+            z: data[3] & 0x40 != 0,
+            ad: data[3] & 0x20 != 0,
+            cd: data[3] & 0x10 != 0,
+            rcode: HeaderRcode::from_bits(data[3]),
 
-            qdcount: u16::from_be_bytes(data[4..=5].try_into().expect("bytes should exist")),
-            ancount: u16::from_be_bytes(data[6..=7].try_into().expect("bytes should exist")),
-            nscount: u16::from_be_bytes(data[8..=9].try_into().expect("bytes should exist")),
-            arcount: u16::from_be_bytes(data[10..=11].try_into().expect("bytes should exist")),
+            qdcount: u16::from_be_bytes(bytes_at(msg, data, 4)?),
+            ancount: u16::from_be_bytes(bytes_at(msg, data, 6)?),
+            nscount: u16::from_be_bytes(bytes_at(msg, data, 8)?),
+            arcount: u16::from_be_bytes(bytes_at(msg, data, 10)?),
         };
-        (&data[12..], h)
-    }
-}
-
-impl Default for DnsHeader {
-    fn default() -> Self {
-        Self {
-            id: 0,
-            qr: 0,
-            opcode: 0,
-            aa: 0,
-            tc: 0,
-            rd: 0,
-            ra: 0,
-            z: 0,
-            rcode: 0,
-            qdcount: 0,
-            ancount: 0,
-            nscount: 0,
-            arcount: 0,
-        }
+        Ok((&data[12..], h))
     }
 }
 
@@ -88,14 +90,16 @@ mod tests {
     fn it_serdes() {
         let h = DnsHeader {
             id: 1234,
-            qr: 1,
-            opcode: 2,
-            aa: 1,
-            tc: 0,
-            rd: 1,
-            ra: 0,     // 0b00000000
-            z: 7,      // 0b00000111
-            rcode: 15, // 0b00001111
+            qr: true,
+            opcode: Opcode::Status,
+            aa: true,
+            tc: false,
+            rd: true,
+            ra: false,
+            z: true,
+            ad: true,
+            cd: true,
+            rcode: HeaderRcode::from_bits(0b00001111),
             qdcount: 2,
             ancount: 2,
             nscount: 7,
@@ -103,8 +107,50 @@ mod tests {
         };
         let expected_bytes = [4, 210, 149, 127, 0, 2, 0, 2, 0, 7, 0, 8];
         assert_eq!(h.serialize(), expected_bytes);
-        let (remainder, dh) = DnsHeader::deserialize(&expected_bytes);
+        let (remainder, dh) = DnsHeader::deserialize(&expected_bytes).unwrap();
         assert_eq!(dh, h);
         assert_eq!(remainder.len(), 0);
     }
+
+    #[test]
+    fn it_rejects_truncated_header() {
+        assert_eq!(
+            DnsHeader::deserialize(&[4, 210, 149]),
+            Err(DnsError::TruncatedHeader { len: 3 })
+        );
+    }
+
+    #[test]
+    fn it_reads_dnssec_flags() {
+        let (_, h) = DnsHeader::deserialize(&[0, 1, 1, 0x30, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert!(h.rd && h.ad && h.cd);
+        assert!(!h.z && !h.ra);
+        assert_eq!(h.rcode, HeaderRcode::default());
+    }
+
+    #[test]
+    fn it_does_not_let_opcode_overflow_into_flags() {
+        let h = DnsHeader {
+            opcode: Opcode::from_bits(0x1F),
+            rcode: HeaderRcode::from_bits(0x1F),
+            ..Default::default()
+        };
+        assert_eq!(h.serialize()[2..4], [0b01111000, 0b00001111]);
+    }
+
+    #[test]
+    fn it_round_trips_every_opcode_and_rcode() {
+        for bits in 0..16 {
+            let h = DnsHeader {
+                qr: true,
+                opcode: Opcode::from_bits(bits),
+                rcode: HeaderRcode::from_bits(bits),
+                ..Default::default()
+            };
+            let (_, dh) = DnsHeader::deserialize(&h.serialize()).unwrap();
+            assert_eq!(dh, h);
+            assert_eq!(dh.opcode.code(), bits);
+            assert_eq!(dh.rcode.bits(), bits);
+        }
+    }
 }
@@ -0,0 +1,57 @@
+/// The 4-bit OPCODE header field. Codes without a variant of their own are `Unknown`, which
+/// can only be built by [`Opcode::from_bits`], so every code has exactly one value.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Opcode {
+    #[default]
+    Query,
+    IQuery, // obsolete (RFC 3425)
+    Status,
+    Notify, // RFC 1996
+    Update, // RFC 2136
+    Unknown(UnknownOpcode),
+}
+
+/// An opcode below 16 that has no variant of its own.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct UnknownOpcode(u8);
+
+impl Opcode {
+    pub fn code(&self) -> u8 {
+        match self {
+            Opcode::Query => 0,
+            Opcode::IQuery => 1,
+            Opcode::Status => 2,
+            Opcode::Notify => 4,
+            Opcode::Update => 5,
+            Opcode::Unknown(UnknownOpcode(code)) => *code,
+        }
+    }
+
+    /// Reads the opcode from the low 4 bits of `bits`.
+    pub fn from_bits(bits: u8) -> Self {
+        match bits & 0b00001111 {
+            0 => Opcode::Query,
+            1 => Opcode::IQuery,
+            2 => Opcode::Status,
+            4 => Opcode::Notify,
+            5 => Opcode::Update,
+            code => Opcode::Unknown(UnknownOpcode(code)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_codes() {
+        for code in 0..16 {
+            assert_eq!(Opcode::from_bits(code).code(), code);
+        }
+        assert_eq!(Opcode::from_bits(4), Opcode::Notify);
+        assert!(matches!(Opcode::from_bits(3), Opcode::Unknown(_)));
+        assert_eq!(Opcode::from_bits(3), Opcode::from_bits(0x13));
+        assert_eq!(Opcode::from_bits(0x15), Opcode::Update);
+    }
+}
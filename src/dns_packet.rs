@@ -1,8 +1,14 @@
 use crate::{
     dns_answer::DnsAnswer,
+    dns_edns::{Edns, MAX_UDP_PAYLOAD, MIN_UDP_PAYLOAD},
+    dns_error::DnsError,
     dns_header::DnsHeader,
+    dns_opcode::Opcode,
     dns_question::DnsQuestion,
-    dns_serde::{DnsDeserialize, DnsSerialize},
+    dns_rcode::Rcode,
+    dns_rdata::RData,
+    dns_serde::{offset_in, DnsDeserialize, DnsSerialize, DnsWriter},
+    label_seq::LabelSeq,
 };
 
 #[derive(Debug, Clone, PartialEq)]
@@ -10,6 +16,9 @@ pub struct DnsPacket {
     pub header: DnsHeader,
     pub questions: Vec<DnsQuestion>,
     pub answers: Option<Vec<DnsAnswer>>,
+    pub authorities: Vec<DnsAnswer>,
+    pub additionals: Vec<DnsAnswer>,
+    pub edns: Option<Edns>, // written as an OPT record at the end of the additional section
 }
 
 impl DnsPacket {
@@ -30,73 +39,234 @@ impl DnsPacket {
             None => 0,
         };
 
+        header.nscount = 0;
+        header.arcount = 0;
+
         Self {
             header,
             questions,
             answers,
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
         }
     }
 
-    pub fn into_parts(self) -> (DnsHeader, Vec<DnsQuestion>, Option<Vec<DnsAnswer>>) {
-        (self.header, self.questions, self.answers)
-    }
-
     pub fn add_answer(&mut self, answer: DnsAnswer) {
         if let Some(ref mut answers) = self.answers {
-            answers.push(answer);
+            if !answers.contains(&answer) {
+                answers.push(answer);
+            }
+        }
+    }
+
+    pub fn add_authority(&mut self, authority: DnsAnswer) {
+        if !self.authorities.contains(&authority) {
+            self.authorities.push(authority);
+        }
+    }
+
+    pub fn add_additional(&mut self, additional: DnsAnswer) {
+        if !self.additionals.contains(&additional) {
+            self.additionals.push(additional);
+        }
+    }
+
+    /// Returns the SOA record a negative (NXDOMAIN or NODATA) response carries in its
+    /// authority section.
+    pub fn negative_soa(&self) -> Option<&DnsAnswer> {
+        self.authorities
+            .iter()
+            .find(|authority| matches!(authority.rdata, RData::Soa(_)))
+    }
+
+    /// Returns the answers for `name`: every record owned by `name` and, if one of them is a
+    /// CNAME, every record owned by the names along the chain it starts.
+    pub fn answers_for(&self, name: &LabelSeq) -> Vec<DnsAnswer> {
+        let mut found: Vec<DnsAnswer> = Vec::new();
+        let Some(answers) = &self.answers else {
+            return found;
+        };
+        let mut current = name.clone();
+        // a chain can't be longer than the answer section, which also stops CNAME loops
+        for _ in 0..answers.len() {
+            let mut target = None;
+            for answer in answers.iter().filter(|a| a.name.eq_ignore_case(&current)) {
+                if let RData::Cname(cname) = &answer.rdata {
+                    target = Some(cname.clone());
+                }
+                if !found.contains(answer) {
+                    found.push(answer.clone());
+                }
+            }
+            match target {
+                Some(cname) => current = cname,
+                None => break,
+            }
         }
+        found
+    }
+
+    /// The full RCODE, including the extended bits carried in the OPT record.
+    pub fn rcode(&self) -> Rcode {
+        let extended_bits = self.edns.as_ref().map_or(0, |edns| edns.extended_rcode);
+        Rcode::from_parts(self.header.rcode, extended_bits)
     }
 
-    pub fn all_questions_answered(&self) -> bool {
-        match self.answers {
-            Some(ref answers) => answers.len() == self.header.qdcount as usize,
-            None => self.header.qdcount == 0,
+    /// Splits `rcode` between the header and the OPT record. Extended codes need an OPT record,
+    /// so set `edns` first; without one they are sent as SERVFAIL rather than cut down to
+    /// their header bits.
+    pub fn set_rcode(&mut self, rcode: Rcode) {
+        let max_code = if self.edns.is_some() { 0x0FFF } else { 0x0F };
+        let rcode = if rcode.code() <= max_code {
+            rcode
+        } else {
+            Rcode::ServFail
+        };
+        self.header.rcode = rcode.header_rcode();
+        if let Some(edns) = self.edns.as_mut() {
+            edns.extended_rcode = rcode.extended_bits();
         }
     }
 
-    pub fn prepare_for_response(&mut self, qr: u8) {
+    pub fn prepare_for_response(&mut self, qr: bool, rcode: Rcode) {
         self.header.qr = qr;
+        if qr {
+            self.edns = self.edns.as_ref().map(Edns::for_response);
+        }
+        self.update_counts();
+        self.set_rcode(rcode);
+    }
+
+    /// Sets the header's section counts to the number of records in each section.
+    fn update_counts(&mut self) {
         self.header.qdcount = self.questions.len() as u16;
         if let Some(ref answers) = self.answers {
             self.header.ancount = answers.len() as u16;
         } else {
             self.header.ancount = 0;
         }
-        self.header.rcode = if self.header.opcode == 0 { 0 } else { 4 }
+        self.header.nscount = self.authorities.len() as u16;
+        self.header.arcount = (self.additionals.len() + usize::from(self.edns.is_some())) as u16;
+    }
+
+    /// Largest response the sender of this query accepts over UDP: 512 bytes, or the payload
+    /// size advertised in its OPT record, capped at what we are willing to send.
+    pub fn max_udp_response_len(&self) -> usize {
+        match &self.edns {
+            Some(edns) => edns
+                .udp_payload_size
+                .clamp(MIN_UDP_PAYLOAD, MAX_UDP_PAYLOAD)
+                .into(),
+            None => MIN_UDP_PAYLOAD.into(),
+        }
+    }
+
+    /// Serializes the packet, dropping records from the end of the message until it fits in
+    /// `limit` bytes. Dropped additional records are only optional data, but dropping an
+    /// answer or authority record sets TC so the client retries over TCP (RFC 2181 section 9).
+    pub fn serialize_within(&mut self, limit: usize) -> Vec<u8> {
+        loop {
+            self.update_counts();
+            let bytes = self.serialize();
+            if bytes.len() <= limit {
+                return bytes;
+            }
+            if self.additionals.pop().is_some() {
+                // optional data, dropped without truncating
+            } else if self.authorities.pop().is_some() {
+                self.header.tc = true;
+            } else if let Some(answers) = self.answers.as_mut().filter(|a| !a.is_empty()) {
+                answers.pop();
+                self.header.tc = true;
+            } else {
+                // the header, questions and OPT record alone don't fit; nothing left to drop
+                return bytes;
+            }
+        }
+    }
+
+    /// Builds a FORMERR response for a query that could not be parsed, keeping whatever
+    /// could be salvaged from the start of the header.
+    pub fn format_error(query_bytes: &[u8]) -> Self {
+        let header = DnsHeader {
+            id: query_bytes
+                .get(..2)
+                .map(|id| u16::from_be_bytes([id[0], id[1]]))
+                .unwrap_or(0),
+            qr: true,
+            opcode: query_bytes
+                .get(2)
+                .map_or(Opcode::Query, |b| Opcode::from_bits(b >> 3)),
+            rd: matches!(query_bytes.get(2), Some(b) if b & 0x01 != 0),
+            ..Default::default()
+        };
+        let mut packet = Self::new(header, vec![], None);
+        packet.set_rcode(Rcode::FormErr);
+        packet
     }
 }
 
 impl DnsSerialize for DnsPacket {
-    fn serialize(&self) -> Vec<u8> {
-        let mut p: Vec<u8> = Vec::new();
-        p.extend_from_slice(&self.header.serialize());
+    fn serialize_to(&self, writer: &mut DnsWriter) {
+        self.header.serialize_to(writer);
         for question in &self.questions {
-            p.extend_from_slice(&question.serialize());
+            question.serialize_to(writer);
         }
         if let Some(answers) = &self.answers {
             for answer in answers {
-                p.extend_from_slice(&answer.serialize());
+                answer.serialize_to(writer);
             }
         }
-        p
+        for authority in &self.authorities {
+            authority.serialize_to(writer);
+        }
+        for additional in &self.additionals {
+            additional.serialize_to(writer);
+        }
+        if let Some(edns) = &self.edns {
+            edns.to_record().serialize_to(writer);
+        }
     }
 }
 
 impl DnsDeserialize for DnsPacket {
-    fn deserialize(data: &[u8]) -> (&[u8], Self) {
-        let (remainder, header) = DnsHeader::deserialize(&data);
+    fn deserialize_in<'a>(msg: &'a [u8], data: &'a [u8]) -> Result<(&'a [u8], Self), DnsError> {
+        let (remainder, header) = DnsHeader::deserialize_in(msg, data)?;
         let (remainder, questions) =
-            DnsQuestion::deserialize_multiple(remainder, header.qdcount as usize);
+            DnsQuestion::deserialize_multiple_in(msg, remainder, header.qdcount as usize)?;
         let (remainder, answers) =
-            DnsAnswer::deserialize_multiple(remainder, header.ancount as usize);
-        (
+            DnsAnswer::deserialize_multiple_in(msg, remainder, header.ancount as usize)?;
+        let (remainder, authorities) =
+            DnsAnswer::deserialize_multiple_in(msg, remainder, header.nscount as usize)?;
+        let additionals_offset = offset_in(msg, remainder);
+        let (remainder, records) =
+            DnsAnswer::deserialize_multiple_in(msg, remainder, header.arcount as usize)?;
+        let mut additionals = Vec::new();
+        let mut edns = None;
+        for record in records {
+            match Edns::from_record(&record) {
+                Some(opt) => {
+                    if edns.replace(opt).is_some() {
+                        return Err(DnsError::DuplicateOpt {
+                            offset: additionals_offset,
+                        });
+                    }
+                }
+                None => additionals.push(record),
+            }
+        }
+        Ok((
             remainder,
             Self {
                 header,
                 questions,
                 answers: Some(answers),
+                authorities,
+                additionals,
+                edns,
             },
-        )
+        ))
     }
 }
 
@@ -106,38 +276,341 @@ impl Default for DnsPacket {
             header: DnsHeader::default(),
             questions: vec![Default::default()],
             answers: None,
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{dns_type::DnsType, label_seq::LabelSeq};
+    use std::net::Ipv4Addr;
 
     use super::*;
+    use crate::dns_rcode::HeaderRcode;
 
     #[test]
     fn it_serdes() {
         let h = DnsHeader {
             id: 1234,
-            qr: 1,
+            qr: true,
             ..Default::default()
         };
         let q = DnsQuestion::default();
         let a = DnsAnswer {
             name: LabelSeq::_new("codecrafters.io"),
-            _type: DnsType::A(8, 8, 8, 8),
+            rdata: RData::A(Ipv4Addr::new(8, 8, 8, 8)),
             ..Default::default()
         };
         let p = DnsPacket::new(h, vec![q], Some(vec![a]));
         let expected_bytes = [
-            4, 210, 128, 0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 12, 99, 111, 100, 101, 99,
-            114, 97, 102, 116, 101, 114, 115, 2, 105, 111, 0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 4, 8, 8,
-            8, 8,
+            4, 210, 128, 0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 1, 0, 1, 12, 99, 111, 100, 101, 99, 114,
+            97, 102, 116, 101, 114, 115, 2, 105, 111, 0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 4, 8, 8, 8, 8,
         ];
         assert_eq!(p.serialize(), expected_bytes);
-        let (remainder, dp) = DnsPacket::deserialize(&expected_bytes);
+        let (remainder, dp) = DnsPacket::deserialize(&expected_bytes).unwrap();
         assert_eq!(dp, p);
         assert_eq!(remainder.len(), 0);
     }
+
+    #[test]
+    fn it_reports_offset_of_malformed_question() {
+        let bytes = [4, 210, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 3, 99, 111];
+        assert_eq!(
+            DnsPacket::deserialize(&bytes),
+            Err(DnsError::UnexpectedEnd {
+                offset: 13,
+                needed: 1
+            })
+        );
+    }
+
+    #[test]
+    fn it_builds_format_error() {
+        let p = DnsPacket::format_error(&[4, 210, 1, 0, 0]);
+        assert_eq!(p.serialize(), [4, 210, 129, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn it_deserializes_compressed_answer() {
+        // response for codecrafters.io where the answer name points back to the question
+        let bytes = [
+            4, 210, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, 12, 99, 111, 100, 101, 99, 114, 97, 102, 116,
+            101, 114, 115, 2, 105, 111, 0, 0, 1, 0, 1, 0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 76,
+            76, 21, 21,
+        ];
+        let (remainder, p) = DnsPacket::deserialize(&bytes).unwrap();
+        assert_eq!(remainder.len(), 0);
+        let answers = p.answers.unwrap();
+        assert_eq!(answers[0].name, LabelSeq::_new("codecrafters.io"));
+        assert_eq!(answers[0].rdata, RData::A(Ipv4Addr::new(76, 76, 21, 21)));
+        assert_eq!(answers[0].ttl, 60);
+    }
+
+    #[test]
+    fn it_compresses_repeated_names() {
+        let h = DnsHeader {
+            id: 1234,
+            qr: true,
+            rd: true,
+            ra: true,
+            ..Default::default()
+        };
+        let q = DnsQuestion {
+            name: LabelSeq::_new("codecrafters.io"),
+            ..Default::default()
+        };
+        let a = DnsAnswer {
+            name: LabelSeq::_new("codecrafters.io"),
+            rdata: RData::A(Ipv4Addr::new(76, 76, 21, 21)),
+            ttl: 60,
+            ..Default::default()
+        };
+        let p = DnsPacket::new(h, vec![q], Some(vec![a]));
+        // same bytes as it_deserializes_compressed_answer
+        let expected_bytes = [
+            4, 210, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, 12, 99, 111, 100, 101, 99, 114, 97, 102, 116,
+            101, 114, 115, 2, 105, 111, 0, 0, 1, 0, 1, 0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 76,
+            76, 21, 21,
+        ];
+        assert_eq!(p.serialize(), expected_bytes);
+        assert_eq!(DnsPacket::deserialize(&expected_bytes).unwrap().1, p);
+
+        let mut w = DnsWriter::_canonical();
+        p.serialize_to(&mut w);
+        let canonical_bytes = w.into_bytes();
+        assert_eq!(canonical_bytes.len(), expected_bytes.len() + 15);
+        assert_eq!(DnsPacket::deserialize(&canonical_bytes).unwrap().1, p);
+    }
+
+    #[test]
+    fn it_deserializes_cname_chain() {
+        // upstream response for www.github.com A: CNAME to github.com, then its A record
+        let bytes = [
+            138, 31, 129, 128, 0, 1, 0, 2, 0, 0, 0, 0, 3, 119, 119, 119, 6, 103, 105, 116, 104,
+            117, 98, 3, 99, 111, 109, 0, 0, 1, 0, 1, 192, 12, 0, 5, 0, 1, 0, 0, 14, 16, 0, 2, 192,
+            16, 192, 16, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 140, 82, 121, 4,
+        ];
+        let (remainder, p) = DnsPacket::deserialize(&bytes).unwrap();
+        assert_eq!(remainder.len(), 0);
+        let chain = p.answers_for(&LabelSeq::_new("WWW.github.com"));
+        assert_eq!(
+            chain.iter().map(|a| &a.rdata).collect::<Vec<_>>(),
+            [
+                &RData::Cname(LabelSeq::_new("github.com")),
+                &RData::A(Ipv4Addr::new(140, 82, 121, 4))
+            ]
+        );
+        assert_eq!(p.serialize(), bytes);
+    }
+
+    #[test]
+    fn it_deserializes_ptr_answer() {
+        // upstream response for 8.8.8.8.in-addr.arpa PTR
+        let bytes = [
+            28, 45, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, 1, 56, 1, 56, 1, 56, 1, 56, 7, 105, 110, 45,
+            97, 100, 100, 114, 4, 97, 114, 112, 97, 0, 0, 12, 0, 1, 192, 12, 0, 12, 0, 1, 0, 0, 78,
+            168, 0, 12, 3, 100, 110, 115, 6, 103, 111, 111, 103, 108, 101, 0,
+        ];
+        let (_, p) = DnsPacket::deserialize(&bytes).unwrap();
+        let answers = p.answers.as_ref().unwrap();
+        assert_eq!(answers[0].rdata, RData::Ptr(LabelSeq::_new("dns.google")));
+        assert_eq!(answers[0].ttl, 20136);
+        assert_eq!(p.serialize(), bytes);
+    }
+
+    #[test]
+    fn it_deserializes_negative_response() {
+        // NXDOMAIN for doesnotexist.google.com with the zone's SOA in the authority section
+        let bytes = [
+            75, 126, 129, 131, 0, 1, 0, 0, 0, 1, 0, 0, 12, 100, 111, 101, 115, 110, 111, 116, 101,
+            120, 105, 115, 116, 6, 103, 111, 111, 103, 108, 101, 3, 99, 111, 109, 0, 0, 1, 0, 1,
+            192, 25, 0, 6, 0, 1, 0, 0, 0, 60, 0, 38, 3, 110, 115, 49, 192, 25, 9, 100, 110, 115,
+            45, 97, 100, 109, 105, 110, 192, 25, 36, 234, 51, 199, 0, 0, 3, 132, 0, 0, 3, 132, 0,
+            0, 7, 8, 0, 0, 0, 60,
+        ];
+        let (remainder, p) = DnsPacket::deserialize(&bytes).unwrap();
+        assert_eq!(remainder.len(), 0);
+        assert_eq!(p.rcode(), Rcode::NXDomain);
+        let soa = p.negative_soa().unwrap();
+        assert_eq!(soa.name, LabelSeq::_new("google.com"));
+        match &soa.rdata {
+            RData::Soa(soa) => assert_eq!(soa.minimum, 60),
+            rdata => panic!("expected SOA, got {:?}", rdata),
+        }
+        assert_eq!(p.serialize(), bytes);
+    }
+
+    #[test]
+    fn it_passes_unknown_types_through() {
+        // HTTPS (type 65) answer for cloudflare.com, which is not modelled
+        let bytes = [
+            1, 1, 129, 128, 0, 1, 0, 1, 0, 0, 0, 0, 10, 99, 108, 111, 117, 100, 102, 108, 97, 114,
+            101, 3, 99, 111, 109, 0, 0, 65, 0, 1, 192, 12, 0, 65, 0, 1, 0, 0, 1, 44, 0, 13, 0, 1,
+            0, 0, 1, 0, 6, 2, 104, 51, 2, 104, 50,
+        ];
+        let (_, p) = DnsPacket::deserialize(&bytes).unwrap();
+        assert_eq!(
+            p.answers.as_ref().unwrap()[0].rdata,
+            RData::Unknown(65, vec![0, 1, 0, 0, 1, 0, 6, 2, 104, 51, 2, 104, 50])
+        );
+        assert_eq!(p.serialize(), bytes);
+    }
+
+    #[test]
+    fn it_serdes_authority_and_additional_sections() {
+        // google.com MX with the zone's NS in the authority section and its glue address
+        let bytes = [
+            34, 34, 129, 128, 0, 1, 0, 1, 0, 1, 0, 1, 6, 103, 111, 111, 103, 108, 101, 3, 99, 111,
+            109, 0, 0, 15, 0, 1, 192, 12, 0, 15, 0, 1, 0, 0, 1, 44, 0, 9, 0, 10, 4, 115, 109, 116,
+            112, 192, 12, 192, 12, 0, 2, 0, 1, 0, 0, 14, 16, 0, 6, 3, 110, 115, 49, 192, 12, 192,
+            61, 0, 1, 0, 1, 0, 0, 14, 16, 0, 4, 216, 239, 32, 10,
+        ];
+        let (remainder, p) = DnsPacket::deserialize(&bytes).unwrap();
+        assert_eq!(remainder.len(), 0);
+        assert_eq!(
+            p.authorities[0].rdata,
+            RData::Ns(LabelSeq::_new("ns1.google.com"))
+        );
+        assert_eq!(p.additionals[0].name, LabelSeq::_new("ns1.google.com"));
+        assert_eq!(
+            p.additionals[0].rdata,
+            RData::A(Ipv4Addr::new(216, 239, 32, 10))
+        );
+        assert_eq!(p.serialize(), bytes);
+    }
+
+    #[test]
+    fn it_keeps_section_counts_in_sync() {
+        let mut p = DnsPacket::new(DnsHeader::default(), vec![], Some(vec![]));
+        p.add_authority(DnsAnswer::default());
+        p.add_additional(DnsAnswer::default());
+        p.add_additional(DnsAnswer::default());
+        p.prepare_for_response(true, Rcode::NoError);
+        assert_eq!(p.header.nscount, 1);
+        assert_eq!(p.header.arcount, 1);
+    }
+
+    #[test]
+    fn it_splits_opt_record_from_additional_section() {
+        // google.com A query advertising a 4096 byte payload with DO set
+        let bytes = [
+            34, 34, 1, 0, 0, 1, 0, 0, 0, 0, 0, 1, 6, 103, 111, 111, 103, 108, 101, 3, 99, 111, 109,
+            0, 0, 1, 0, 1, 0, 0, 41, 16, 0, 0, 0, 128, 0, 0, 0,
+        ];
+        let (remainder, mut p) = DnsPacket::deserialize(&bytes).unwrap();
+        assert_eq!(remainder.len(), 0);
+        assert!(p.additionals.is_empty());
+        let edns = p.edns.as_ref().unwrap();
+        assert_eq!(edns.udp_payload_size, 4096);
+        assert!(edns.dnssec_ok());
+        assert_eq!(p.serialize(), bytes);
+
+        p.prepare_for_response(true, Rcode::NoError);
+        assert_eq!(p.header.arcount, 1);
+        assert_eq!(p.edns.unwrap().udp_payload_size, MAX_UDP_PAYLOAD);
+    }
+
+    #[test]
+    fn it_rejects_duplicate_opt_records() {
+        let bytes = [
+            34, 34, 1, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 41, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 41, 16,
+            0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(
+            DnsPacket::deserialize(&bytes),
+            Err(DnsError::DuplicateOpt { offset: 12 })
+        );
+    }
+
+    fn a_records(count: u8) -> Vec<DnsAnswer> {
+        (0..count)
+            .map(|i| DnsAnswer {
+                name: LabelSeq::_new("google.com"),
+                rdata: RData::A(Ipv4Addr::new(10, 0, 0, i)),
+                ttl: 60,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_truncates_oversized_responses() {
+        let q = DnsQuestion {
+            name: LabelSeq::_new("google.com"),
+            ..Default::default()
+        };
+        // 28 bytes of header and question, then 16 bytes per compressed A record
+        let mut p = DnsPacket::new(DnsHeader::default(), vec![q], Some(a_records(40)));
+        let limit = p.max_udp_response_len();
+        assert_eq!(limit, 512);
+        p.prepare_for_response(true, Rcode::NoError);
+        let bytes = p.serialize_within(limit);
+        assert_eq!(bytes.len(), 508);
+        let (_, truncated) = DnsPacket::deserialize(&bytes).unwrap();
+        assert!(truncated.header.tc);
+        assert_eq!(truncated.answers.unwrap().len(), 30);
+    }
+
+    #[test]
+    fn it_drops_additional_records_without_truncating() {
+        let mut p = DnsPacket::new(DnsHeader::default(), vec![], Some(a_records(1)));
+        p.additionals = a_records(40);
+        p.edns = Some(Edns::for_query(false));
+        p.prepare_for_response(true, Rcode::NoError);
+        let bytes = p.serialize_within(MIN_UDP_PAYLOAD.into());
+        let (_, truncated) = DnsPacket::deserialize(&bytes).unwrap();
+        assert!(!truncated.header.tc);
+        assert_eq!(truncated.answers.unwrap().len(), 1);
+        assert!(truncated.additionals.len() < 40);
+        assert!(truncated.edns.is_some());
+    }
+
+    #[test]
+    fn it_truncates_with_stale_section_counts() {
+        let mut p = DnsPacket::new(DnsHeader::default(), vec![], Some(a_records(40)));
+        p.additionals = a_records(40);
+        p.authorities = a_records(40);
+        // the authority and additional counts are still zero
+        let bytes = p.serialize_within(MIN_UDP_PAYLOAD.into());
+        let (_, truncated) = DnsPacket::deserialize(&bytes).unwrap();
+        assert!(truncated.header.tc);
+        assert!(truncated.additionals.is_empty());
+        assert_eq!(truncated.answers.unwrap().len(), 30);
+    }
+
+    #[test]
+    fn it_returns_every_record_for_the_name() {
+        let mut answers = a_records(3);
+        answers.push(DnsAnswer {
+            name: LabelSeq::_new("example.com"),
+            ..Default::default()
+        });
+        let p = DnsPacket::new(DnsHeader::default(), vec![], Some(answers));
+        assert_eq!(p.answers_for(&LabelSeq::_new("google.com")), a_records(3));
+    }
+
+    #[test]
+    fn it_carries_extended_rcode_in_opt_record() {
+        let mut p = DnsPacket::new(DnsHeader::default(), vec![], Some(vec![]));
+        p.edns = Some(Edns::for_query(false));
+        p.prepare_for_response(true, Rcode::BadVers);
+        assert_eq!(p.header.rcode, Rcode::NoError.header_rcode());
+        let (_, dp) = DnsPacket::deserialize(&p.serialize()).unwrap();
+        assert_eq!(dp.rcode(), Rcode::BadVers);
+
+        let largest = Rcode::from_parts(HeaderRcode::from_bits(0x0F), 0xFF);
+        p.set_rcode(largest);
+        let (_, dp) = DnsPacket::deserialize(&p.serialize()).unwrap();
+        assert_eq!(dp.rcode().code(), 0x0FFF);
+    }
+
+    #[test]
+    fn it_answers_servfail_for_extended_rcode_without_opt_record() {
+        let mut p = DnsPacket::new(DnsHeader::default(), vec![], Some(vec![]));
+        p.prepare_for_response(true, Rcode::BadVers);
+        assert_eq!(p.header.rcode, Rcode::ServFail.header_rcode());
+        assert_eq!(p.rcode(), Rcode::ServFail);
+    }
 }
@@ -1,39 +1,46 @@
-use crate::dns_serde::{DnsDeserialize, DnsSerialize};
-use crate::dns_type::DnsType;
+use crate::dns_error::DnsError;
+use crate::dns_serde::{bytes_at, DnsDeserialize, DnsSerialize, DnsWriter};
+use crate::dns_type::RecordType;
 use crate::label_seq::LabelSeq;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct DnsQuestion {
     pub name: LabelSeq,
-    pub _type: DnsType,
+    pub _type: RecordType,
     pub _class: u16,
 }
 
-impl DnsSerialize for DnsQuestion {
-    fn serialize(&self) -> Vec<u8> {
-        let mut v: Vec<u8> = Vec::new();
+impl DnsQuestion {
+    /// Whether `other` asks the same question, comparing names case-insensitively.
+    pub fn matches(&self, other: &DnsQuestion) -> bool {
+        self.name.eq_ignore_case(&other.name)
+            && self._type == other._type
+            && self._class == other._class
+    }
+}
 
+impl DnsSerialize for DnsQuestion {
+    fn serialize_to(&self, writer: &mut DnsWriter) {
         // serialize the name
-        v.extend_from_slice(&self.name.serialize());
-        v.extend_from_slice(&self._type.int_as_bytes());
-        v.extend_from_slice(&self._class.to_be_bytes());
-        v
+        self.name.serialize_to(writer);
+        writer.extend(&self._type.int_as_bytes());
+        writer.extend(&self._class.to_be_bytes());
     }
 }
 
 impl DnsDeserialize for DnsQuestion {
-    fn deserialize(data: &[u8]) -> (&[u8], Self) {
-        let (remainder, name) = LabelSeq::deserialize(data);
-        let _type = DnsType::from_bytes(remainder[..2].try_into().expect("Invalid data"));
-        let _class = u16::from_be_bytes(remainder[2..4].try_into().expect("Invalid data"));
-        (
+    fn deserialize_in<'a>(msg: &'a [u8], data: &'a [u8]) -> Result<(&'a [u8], Self), DnsError> {
+        let (remainder, name) = LabelSeq::deserialize_in(msg, data)?;
+        let _type = RecordType::from_bytes(bytes_at(msg, remainder, 0)?);
+        let _class = u16::from_be_bytes(bytes_at(msg, remainder, 2)?);
+        Ok((
             &remainder[4..],
             Self {
                 name,
                 _type,
                 _class,
             },
-        )
+        ))
     }
 }
 
@@ -41,7 +48,7 @@ impl Default for DnsQuestion {
     fn default() -> Self {
         Self {
             name: LabelSeq::default(),
-            _type: DnsType::default(),
+            _type: RecordType::default(),
             _class: 1,
         }
     }
@@ -61,8 +68,55 @@ mod tests {
             12, 99, 111, 100, 101, 99, 114, 97, 102, 116, 101, 114, 115, 2, 105, 111, 0, 0, 1, 0, 1,
         ];
         assert_eq!(q.serialize(), expected_bytes);
-        let (remainder, dq) = DnsQuestion::deserialize(&expected_bytes);
+        let (remainder, dq) = DnsQuestion::deserialize(&expected_bytes).unwrap();
         assert_eq!(remainder.len(), 0);
         assert_eq!(dq, q);
     }
+
+    #[test]
+    fn it_rejects_missing_class() {
+        let bytes = [6, 103, 111, 111, 103, 108, 101, 3, 99, 111, 109, 0, 0, 1, 0];
+        assert_eq!(
+            DnsQuestion::deserialize(&bytes),
+            Err(DnsError::UnexpectedEnd {
+                offset: 14,
+                needed: 1
+            })
+        );
+    }
+
+    #[test]
+    fn it_keeps_question_type() {
+        let bytes = [
+            6, 103, 111, 111, 103, 108, 101, 3, 99, 111, 109, 0, 0, 28, 0, 1,
+        ];
+        let (_, q) = DnsQuestion::deserialize(&bytes).unwrap();
+        assert_eq!(q._type, RecordType::Aaaa);
+        assert_eq!(q.serialize(), bytes);
+
+        let bytes = [
+            6, 103, 111, 111, 103, 108, 101, 3, 99, 111, 109, 0, 0xFF, 0x00, 0, 1,
+        ];
+        let (_, q) = DnsQuestion::deserialize(&bytes).unwrap();
+        assert_eq!(q._type, RecordType::Unknown(0xFF00));
+        assert_eq!(q.serialize(), bytes);
+    }
+
+    #[test]
+    fn it_matches_questions_ignoring_case() {
+        let q = DnsQuestion {
+            name: LabelSeq::_new("codecrafters.io"),
+            ..Default::default()
+        };
+        let upper = DnsQuestion {
+            name: LabelSeq::_new("CodeCrafters.IO"),
+            ..Default::default()
+        };
+        let aaaa = DnsQuestion {
+            _type: RecordType::Aaaa,
+            ..q.clone()
+        };
+        assert!(q.matches(&upper));
+        assert!(!q.matches(&aaaa));
+    }
 }
@@ -0,0 +1,105 @@
+/// The 12-bit RCODE: the low 4 bits live in the header and, with EDNS, the high 8 bits in the
+/// OPT record (RFC 6891 section 6.1.3). Codes without a variant of their own are `Unknown`,
+/// which can only be built by [`Rcode::from_parts`], so every code has exactly one value.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Rcode {
+    #[default]
+    NoError,
+    FormErr,
+    ServFail,
+    NXDomain,
+    NotImp,
+    Refused,
+    BadVers,   // EDNS version not supported
+    BadCookie, // RFC 7873
+    Unknown(UnknownRcode),
+}
+
+/// An RCODE of at most 4095 that has no variant of its own.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct UnknownRcode(u16);
+
+/// The 4 RCODE bits in the header. The full [`Rcode`] also needs the bits in the OPT record,
+/// so it is only read and written through the packet.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct HeaderRcode(u8);
+
+impl HeaderRcode {
+    /// Reads the rcode from the low 4 bits of `bits`.
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits & 0x0F)
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+}
+
+impl Rcode {
+    pub fn code(&self) -> u16 {
+        match self {
+            Rcode::NoError => 0,
+            Rcode::FormErr => 1,
+            Rcode::ServFail => 2,
+            Rcode::NXDomain => 3,
+            Rcode::NotImp => 4,
+            Rcode::Refused => 5,
+            Rcode::BadVers => 16,
+            Rcode::BadCookie => 23,
+            Rcode::Unknown(UnknownRcode(code)) => *code,
+        }
+    }
+
+    /// The low 4 bits, written to the header.
+    pub fn header_rcode(&self) -> HeaderRcode {
+        HeaderRcode::from_bits(self.code() as u8)
+    }
+
+    /// The high 8 bits, written to the OPT record.
+    pub fn extended_bits(&self) -> u8 {
+        (self.code() >> 4) as u8
+    }
+
+    pub fn from_parts(header_rcode: HeaderRcode, extended_bits: u8) -> Self {
+        match (extended_bits as u16) << 4 | header_rcode.bits() as u16 {
+            0 => Rcode::NoError,
+            1 => Rcode::FormErr,
+            2 => Rcode::ServFail,
+            3 => Rcode::NXDomain,
+            4 => Rcode::NotImp,
+            5 => Rcode::Refused,
+            16 => Rcode::BadVers,
+            23 => Rcode::BadCookie,
+            code => Rcode::Unknown(UnknownRcode(code)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_codes() {
+        for code in 0..4096 {
+            let header_rcode = HeaderRcode::from_bits(code as u8);
+            let rcode = Rcode::from_parts(header_rcode, (code >> 4) as u8);
+            assert_eq!(rcode.code(), code);
+        }
+        let from_bits = |bits| Rcode::from_parts(HeaderRcode::from_bits(bits), 0);
+        assert_eq!(from_bits(3), Rcode::NXDomain);
+        assert!(matches!(from_bits(12), Rcode::Unknown(_)));
+        assert_eq!(from_bits(0x12), Rcode::ServFail);
+    }
+
+    #[test]
+    fn it_splits_extended_codes() {
+        assert_eq!(Rcode::BadVers.header_rcode(), HeaderRcode::from_bits(0));
+        assert_eq!(Rcode::BadVers.extended_bits(), 1);
+        assert_eq!(
+            Rcode::from_parts(HeaderRcode::from_bits(0), 1),
+            Rcode::BadVers
+        );
+        assert_eq!(Rcode::NXDomain.header_rcode().bits(), 3);
+    }
+}
@@ -0,0 +1,656 @@
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use crate::{
+    dns_edns::EdnsOption,
+    dns_error::DnsError,
+    dns_serde::{bytes_at, offset_in, DnsDeserialize, DnsSerialize, DnsWriter},
+    dns_type::RecordType,
+    label_seq::LabelSeq,
+};
+
+/// The RDATA of a resource record, typed according to its record type.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ns(LabelSeq),
+    Cname(LabelSeq),
+    Soa(Soa),
+    Ptr(LabelSeq),
+    Mx(Mx),
+    Txt(Txt),
+    Srv(Srv),
+    /// Options of the EDNS(0) OPT pseudo-record, see `Edns`.
+    Opt(Vec<EdnsOption>),
+    /// RDATA of a type this crate does not model, kept byte for byte (RFC 3597).
+    Unknown(u16, Vec<u8>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Soa {
+    pub mname: LabelSeq,
+    pub rname: LabelSeq,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32, // also the TTL for negative caching (RFC 2308)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Mx {
+    pub preference: u16,
+    pub exchange: LabelSeq,
+}
+
+/// TXT data: one or more character-strings of at most 255 bytes each.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Txt {
+    pub strings: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Srv {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: LabelSeq,
+}
+
+/// A character-string is a length byte followed by at most 255 bytes.
+const MAX_CHARACTER_STRING_LEN: usize = 255;
+
+impl RData {
+    pub fn record_type(&self) -> RecordType {
+        match self {
+            RData::A(_) => RecordType::A,
+            RData::Aaaa(_) => RecordType::Aaaa,
+            RData::Ns(_) => RecordType::Ns,
+            RData::Cname(_) => RecordType::Cname,
+            RData::Soa(_) => RecordType::Soa,
+            RData::Ptr(_) => RecordType::Ptr,
+            RData::Mx(_) => RecordType::Mx,
+            RData::Txt(_) => RecordType::Txt,
+            RData::Srv(_) => RecordType::Srv,
+            RData::Opt(_) => RecordType::Opt,
+            RData::Unknown(code, _) => RecordType::from(*code),
+        }
+    }
+
+    /// Writes RDLENGTH followed by RDATA. The length is patched in afterwards since names
+    /// in the data may be compressed.
+    pub fn serialize_length_and_data_to(&self, writer: &mut DnsWriter) {
+        let length_offset = writer.len();
+        writer.extend(&[0, 0]);
+        self.serialize_to(writer);
+        let rdlength = writer.len() - length_offset - 2;
+        writer.patch_u16(
+            length_offset,
+            rdlength
+                .try_into()
+                .expect("rdata length should fit in 2 bytes"),
+        );
+    }
+
+    /// Deserializes RDLENGTH and RDATA from `length_and_data_bytes`, a suffix of `msg`.
+    /// Names in the data may be compressed, so they are parsed against the whole message and
+    /// the number of bytes consumed is checked against RDLENGTH afterwards.
+    pub fn deserialize<'a>(
+        msg: &'a [u8],
+        record_type: RecordType,
+        length_and_data_bytes: &'a [u8],
+    ) -> Result<(&'a [u8], Self), DnsError> {
+        let rdlength = u16::from_be_bytes(bytes_at(msg, length_and_data_bytes, 0)?);
+        let data = &length_and_data_bytes[2..];
+        if data.len() < rdlength as usize {
+            return Err(DnsError::UnexpectedEnd {
+                offset: offset_in(msg, data),
+                needed: rdlength as usize - data.len(),
+            });
+        }
+        let (remainder, rdata) = match record_type {
+            RecordType::A => {
+                let octets: [u8; 4] = bytes_at(msg, data, 0)?;
+                (&data[4..], RData::A(octets.into()))
+            }
+            RecordType::Aaaa => {
+                let octets: [u8; 16] = bytes_at(msg, data, 0)?;
+                (&data[16..], RData::Aaaa(octets.into()))
+            }
+            RecordType::Ns => Self::deserialize_name(msg, data, RData::Ns)?,
+            RecordType::Cname => Self::deserialize_name(msg, data, RData::Cname)?,
+            RecordType::Ptr => Self::deserialize_name(msg, data, RData::Ptr)?,
+            RecordType::Soa => {
+                let (remainder, soa) = Soa::deserialize_in(msg, data)?;
+                (remainder, RData::Soa(soa))
+            }
+            RecordType::Mx => {
+                let (remainder, mx) = Mx::deserialize_in(msg, data)?;
+                (remainder, RData::Mx(mx))
+            }
+            RecordType::Txt => {
+                let (remainder, txt) = Txt::deserialize_in(msg, data, rdlength as usize)?;
+                (remainder, RData::Txt(txt))
+            }
+            RecordType::Srv => {
+                let (remainder, srv) = Srv::deserialize_in(msg, data)?;
+                (remainder, RData::Srv(srv))
+            }
+            RecordType::Opt => {
+                let mut options = Vec::new();
+                let mut remainder = data;
+                while data.len() - remainder.len() < rdlength as usize {
+                    let (next, option) = EdnsOption::deserialize_in(msg, remainder)?;
+                    options.push(option);
+                    remainder = next;
+                }
+                (remainder, RData::Opt(options))
+            }
+            _ => {
+                let rdata = data[..rdlength as usize].to_vec();
+                (
+                    &data[rdlength as usize..],
+                    RData::Unknown(record_type.code(), rdata),
+                )
+            }
+        };
+        if data.len() - remainder.len() != rdlength as usize {
+            return Err(DnsError::BadRdLength {
+                offset: offset_in(msg, length_and_data_bytes),
+                rdlength,
+            });
+        }
+        Ok((remainder, rdata))
+    }
+
+    fn deserialize_name<'a>(
+        msg: &'a [u8],
+        data: &'a [u8],
+        variant: fn(LabelSeq) -> Self,
+    ) -> Result<(&'a [u8], Self), DnsError> {
+        let (remainder, name) = LabelSeq::deserialize_in(msg, data)?;
+        Ok((remainder, variant(name)))
+    }
+}
+
+impl DnsSerialize for RData {
+    fn serialize_to(&self, writer: &mut DnsWriter) {
+        match self {
+            RData::A(addr) => writer.extend(&addr.octets()),
+            RData::Aaaa(addr) => writer.extend(&addr.octets()),
+            RData::Ns(name) | RData::Cname(name) | RData::Ptr(name) => name.serialize_to(writer),
+            RData::Soa(soa) => soa.serialize_to(writer),
+            RData::Mx(mx) => mx.serialize_to(writer),
+            RData::Txt(txt) => txt.serialize_to(writer),
+            RData::Srv(srv) => srv.serialize_to(writer),
+            RData::Opt(options) => {
+                for option in options {
+                    option.serialize_to(writer);
+                }
+            }
+            RData::Unknown(_, data) => writer.extend(data),
+        }
+    }
+}
+
+impl DnsSerialize for Soa {
+    fn serialize_to(&self, writer: &mut DnsWriter) {
+        self.mname.serialize_to(writer);
+        self.rname.serialize_to(writer);
+        writer.extend(&self.serial.to_be_bytes());
+        writer.extend(&self.refresh.to_be_bytes());
+        writer.extend(&self.retry.to_be_bytes());
+        writer.extend(&self.expire.to_be_bytes());
+        writer.extend(&self.minimum.to_be_bytes());
+    }
+}
+
+impl DnsDeserialize for Soa {
+    fn deserialize_in<'a>(msg: &'a [u8], data: &'a [u8]) -> Result<(&'a [u8], Self), DnsError> {
+        let (remainder, mname) = LabelSeq::deserialize_in(msg, data)?;
+        let (remainder, rname) = LabelSeq::deserialize_in(msg, remainder)?;
+        let soa = Self {
+            mname,
+            rname,
+            serial: u32::from_be_bytes(bytes_at(msg, remainder, 0)?),
+            refresh: u32::from_be_bytes(bytes_at(msg, remainder, 4)?),
+            retry: u32::from_be_bytes(bytes_at(msg, remainder, 8)?),
+            expire: u32::from_be_bytes(bytes_at(msg, remainder, 12)?),
+            minimum: u32::from_be_bytes(bytes_at(msg, remainder, 16)?),
+        };
+        Ok((&remainder[20..], soa))
+    }
+}
+
+impl DnsSerialize for Mx {
+    fn serialize_to(&self, writer: &mut DnsWriter) {
+        writer.extend(&self.preference.to_be_bytes());
+        self.exchange.serialize_to(writer);
+    }
+}
+
+impl DnsDeserialize for Mx {
+    fn deserialize_in<'a>(msg: &'a [u8], data: &'a [u8]) -> Result<(&'a [u8], Self), DnsError> {
+        let preference = u16::from_be_bytes(bytes_at(msg, data, 0)?);
+        let (remainder, exchange) = LabelSeq::deserialize_in(msg, &data[2..])?;
+        Ok((
+            remainder,
+            Self {
+                preference,
+                exchange,
+            },
+        ))
+    }
+}
+
+impl Txt {
+    /// Splits `text` into as many character-strings as needed to fit 255 bytes each.
+    pub fn _from_text(text: &[u8]) -> Self {
+        if text.is_empty() {
+            return Self {
+                strings: vec![Vec::new()],
+            };
+        }
+        Self {
+            strings: text
+                .chunks(MAX_CHARACTER_STRING_LEN)
+                .map(|chunk| chunk.to_vec())
+                .collect(),
+        }
+    }
+
+    /// The character-strings the TXT data is written as: each string split into chunks of at
+    /// most 255 bytes, with empty strings kept.
+    fn character_strings(&self) -> impl Iterator<Item = &[u8]> {
+        self.strings.iter().flat_map(|string| {
+            let empty = string.is_empty().then_some(&string[..]);
+            string.chunks(MAX_CHARACTER_STRING_LEN).chain(empty)
+        })
+    }
+
+    /// TXT data is not self-delimiting, so character-strings are read until `rdlength` bytes
+    /// have been consumed.
+    fn deserialize_in<'a>(
+        msg: &'a [u8],
+        data: &'a [u8],
+        rdlength: usize,
+    ) -> Result<(&'a [u8], Self), DnsError> {
+        let mut strings = Vec::new();
+        let mut remainder = data;
+        while data.len() - remainder.len() < rdlength {
+            let [len] = bytes_at(msg, remainder, 0)?;
+            let string =
+                remainder
+                    .get(1..=len as usize)
+                    .ok_or_else(|| DnsError::UnexpectedEnd {
+                        offset: offset_in(msg, remainder) + 1,
+                        needed: len as usize + 1 - remainder.len(),
+                    })?;
+            strings.push(string.to_vec());
+            remainder = &remainder[len as usize + 1..];
+        }
+        Ok((remainder, Self { strings }))
+    }
+}
+
+impl DnsSerialize for Txt {
+    fn serialize_to(&self, writer: &mut DnsWriter) {
+        for string in self.character_strings() {
+            writer.push(string.len() as u8);
+            writer.extend(string);
+        }
+    }
+}
+
+impl DnsSerialize for Srv {
+    fn serialize_to(&self, writer: &mut DnsWriter) {
+        writer.extend(&self.priority.to_be_bytes());
+        writer.extend(&self.weight.to_be_bytes());
+        writer.extend(&self.port.to_be_bytes());
+        // RFC 2782: the target must not be compressed
+        self.target.serialize_uncompressed_to(writer);
+    }
+}
+
+impl DnsDeserialize for Srv {
+    fn deserialize_in<'a>(msg: &'a [u8], data: &'a [u8]) -> Result<(&'a [u8], Self), DnsError> {
+        let priority = u16::from_be_bytes(bytes_at(msg, data, 0)?);
+        let weight = u16::from_be_bytes(bytes_at(msg, data, 2)?);
+        let port = u16::from_be_bytes(bytes_at(msg, data, 4)?);
+        let (remainder, target) = LabelSeq::deserialize_in(msg, &data[6..])?;
+        Ok((
+            remainder,
+            Self {
+                priority,
+                weight,
+                port,
+                target,
+            },
+        ))
+    }
+}
+
+/// Presentation format as used in master files, e.g. `10 smtp.google.com.` for MX.
+impl fmt::Display for RData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RData::A(addr) => write!(f, "{}", addr),
+            RData::Aaaa(addr) => write!(f, "{}", addr),
+            RData::Ns(name) | RData::Cname(name) | RData::Ptr(name) => write!(f, "{}", name),
+            RData::Soa(soa) => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                soa.mname, soa.rname, soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum
+            ),
+            RData::Mx(mx) => write!(f, "{} {}", mx.preference, mx.exchange),
+            RData::Txt(txt) => {
+                for (i, string) in txt.character_strings().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "\"")?;
+                    for byte in string {
+                        match byte {
+                            b'"' | b'\\' => write!(f, "\\{}", *byte as char)?,
+                            0x20..=0x7E => write!(f, "{}", *byte as char)?,
+                            _ => write!(f, "\\{:03}", byte)?,
+                        }
+                    }
+                    write!(f, "\"")?;
+                }
+                Ok(())
+            }
+            RData::Srv(srv) => write!(
+                f,
+                "{} {} {} {}",
+                srv.priority, srv.weight, srv.port, srv.target
+            ),
+            RData::Opt(options) => {
+                for (i, option) in options.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}:", option.code)?;
+                    for byte in &option.data {
+                        write!(f, "{:02x}", byte)?;
+                    }
+                }
+                Ok(())
+            }
+            // RFC 3597 section 5: \# <length> <hex>
+            RData::Unknown(_, data) => {
+                write!(f, "\\# {}", data.len())?;
+                if !data.is_empty() {
+                    write!(f, " ")?;
+                    for byte in data {
+                        write!(f, "{:02x}", byte)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Default for RData {
+    fn default() -> Self {
+        RData::A(Ipv4Addr::UNSPECIFIED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_serdes() {
+        let r = RData::A(Ipv4Addr::new(8, 8, 8, 8));
+
+        let expected_bytes = [0, 4, 8, 8, 8, 8];
+        let mut w = DnsWriter::new();
+        r.serialize_length_and_data_to(&mut w);
+        assert_eq!(w.into_bytes(), expected_bytes);
+        assert_eq!(
+            RData::deserialize(&expected_bytes, RecordType::A, &expected_bytes)
+                .unwrap()
+                .1,
+            r
+        );
+    }
+
+    #[test]
+    fn it_serdes_aaaa() {
+        let r = RData::Aaaa("2001:4860:4860::8888".parse().unwrap());
+
+        let expected_bytes = [
+            0, 16, 0x20, 0x01, 0x48, 0x60, 0x48, 0x60, 0, 0, 0, 0, 0, 0, 0, 0, 0x88, 0x88,
+        ];
+        let mut w = DnsWriter::new();
+        r.serialize_length_and_data_to(&mut w);
+        assert_eq!(w.into_bytes(), expected_bytes);
+        assert_eq!(
+            RData::deserialize(&expected_bytes, RecordType::Aaaa, &expected_bytes)
+                .unwrap()
+                .1,
+            r
+        );
+    }
+
+    #[test]
+    fn it_rejects_short_aaaa() {
+        let bytes = [0, 4, 8, 8, 8, 8];
+        assert_eq!(
+            RData::deserialize(&bytes, RecordType::Aaaa, &bytes),
+            Err(DnsError::UnexpectedEnd {
+                offset: 2,
+                needed: 12
+            })
+        );
+    }
+
+    #[test]
+    fn it_serdes_names() {
+        for r in [
+            RData::Ns(LabelSeq::_new("ns1.google.com")),
+            RData::Cname(LabelSeq::_new("github.com")),
+            RData::Ptr(LabelSeq::_new("dns.google")),
+        ] {
+            let mut w = DnsWriter::new();
+            r.serialize_length_and_data_to(&mut w);
+            let bytes = w.into_bytes();
+            let (remainder, dr) = RData::deserialize(&bytes, r.record_type(), &bytes).unwrap();
+            assert_eq!(dr, r);
+            assert_eq!(remainder.len(), 0);
+        }
+    }
+
+    #[test]
+    fn it_compresses_names_in_rdata() {
+        let mut w = DnsWriter::new();
+        LabelSeq::_new("www.github.com").serialize_to(&mut w);
+        RData::Cname(LabelSeq::_new("github.com")).serialize_length_and_data_to(&mut w);
+        let bytes = w.into_bytes();
+        assert_eq!(&bytes[16..], [0, 2, 0xC0, 4]);
+        let (remainder, r) = RData::deserialize(&bytes, RecordType::Cname, &bytes[16..]).unwrap();
+        assert_eq!(r, RData::Cname(LabelSeq::_new("github.com")));
+        assert_eq!(remainder.len(), 0);
+    }
+
+    #[test]
+    fn it_serdes_soa() {
+        let r = RData::Soa(Soa {
+            mname: LabelSeq::_new("ns1.google.com"),
+            rname: LabelSeq::_new("dns-admin.google.com"),
+            serial: 619328455,
+            refresh: 900,
+            retry: 900,
+            expire: 1800,
+            minimum: 60,
+        });
+        let mut w = DnsWriter::new();
+        r.serialize_length_and_data_to(&mut w);
+        let bytes = w.into_bytes();
+        // rname shares "google.com" with mname
+        assert_eq!(bytes.len(), 2 + 16 + 12 + 20);
+        let (remainder, dr) = RData::deserialize(&bytes, RecordType::Soa, &bytes).unwrap();
+        assert_eq!(dr, r);
+        assert_eq!(remainder.len(), 0);
+    }
+
+    #[test]
+    fn it_serdes_mx() {
+        let r = RData::Mx(Mx {
+            preference: 10,
+            exchange: LabelSeq::_new("smtp.google.com"),
+        });
+        let expected_bytes = [
+            0, 19, 0, 10, 4, 115, 109, 116, 112, 6, 103, 111, 111, 103, 108, 101, 3, 99, 111, 109,
+            0,
+        ];
+        let mut w = DnsWriter::new();
+        r.serialize_length_and_data_to(&mut w);
+        assert_eq!(w.into_bytes(), expected_bytes);
+        let (remainder, dr) =
+            RData::deserialize(&expected_bytes, RecordType::Mx, &expected_bytes).unwrap();
+        assert_eq!(dr, r);
+        assert_eq!(remainder.len(), 0);
+    }
+
+    #[test]
+    fn it_serdes_srv_without_compressing_target() {
+        let target = LabelSeq::_new("xmpp.example.com");
+        let r = RData::Srv(Srv {
+            priority: 5,
+            weight: 0,
+            port: 5269,
+            target: target.clone(),
+        });
+        let mut w = DnsWriter::new();
+        target.serialize_to(&mut w);
+        r.serialize_length_and_data_to(&mut w);
+        let bytes = w.into_bytes();
+        let rdata_offset = target.serialize().len();
+        assert_eq!(&bytes[rdata_offset..rdata_offset + 2], [0, 24]);
+        assert_eq!(&bytes[rdata_offset + 8..], target.serialize());
+        let (remainder, dr) =
+            RData::deserialize(&bytes, RecordType::Srv, &bytes[rdata_offset..]).unwrap();
+        assert_eq!(dr, r);
+        assert_eq!(remainder.len(), 0);
+    }
+
+    #[test]
+    fn it_serdes_txt() {
+        let r = RData::Txt(Txt {
+            strings: vec![b"v=spf1".to_vec(), b"-all".to_vec()],
+        });
+        let expected_bytes = [
+            0, 12, 6, 118, 61, 115, 112, 102, 49, 4, 45, 97, 108, 108, 0xAB,
+        ];
+        let mut w = DnsWriter::new();
+        r.serialize_length_and_data_to(&mut w);
+        assert_eq!(w.into_bytes(), expected_bytes[..14]);
+        let (remainder, dr) =
+            RData::deserialize(&expected_bytes, RecordType::Txt, &expected_bytes).unwrap();
+        assert_eq!(dr, r);
+        assert_eq!(remainder, [0xAB]);
+    }
+
+    #[test]
+    fn it_splits_long_txt_into_character_strings() {
+        let text = [b'x'; 600];
+        let txt = Txt::_from_text(&text);
+        assert_eq!(
+            txt.strings.iter().map(|s| s.len()).collect::<Vec<_>>(),
+            [255, 255, 90]
+        );
+        let r = RData::Txt(txt);
+        let mut w = DnsWriter::new();
+        r.serialize_length_and_data_to(&mut w);
+        let bytes = w.into_bytes();
+        assert_eq!(bytes.len(), 2 + 600 + 3);
+        assert_eq!(bytes[2], 255);
+        assert_eq!(bytes[2 + 256], 255);
+        assert_eq!(bytes[2 + 512], 90);
+        assert_eq!(
+            RData::deserialize(&bytes, RecordType::Txt, &bytes)
+                .unwrap()
+                .1,
+            r
+        );
+    }
+
+    #[test]
+    fn it_rejects_txt_overrunning_rdlength() {
+        let bytes = [0, 3, 6, 118, 61, 115, 112, 102, 49];
+        assert_eq!(
+            RData::deserialize(&bytes, RecordType::Txt, &bytes),
+            Err(DnsError::BadRdLength {
+                offset: 0,
+                rdlength: 3
+            })
+        );
+    }
+
+    #[test]
+    fn it_rejects_name_overrunning_rdlength() {
+        let bytes = [0, 3, 3, 99, 111, 109, 0];
+        assert_eq!(
+            RData::deserialize(&bytes, RecordType::Cname, &bytes),
+            Err(DnsError::BadRdLength {
+                offset: 0,
+                rdlength: 3
+            })
+        );
+    }
+
+    #[test]
+    fn it_keeps_unknown_types_opaque() {
+        let bytes = [0, 2, 0, 10, 0xAB];
+        let (remainder, r) = RData::deserialize(&bytes, RecordType::Unknown(99), &bytes).unwrap();
+        assert_eq!(r, RData::Unknown(99, vec![0, 10]));
+        assert_eq!(r.record_type(), RecordType::Unknown(99));
+        assert_eq!(remainder, [0xAB]);
+        let mut w = DnsWriter::new();
+        r.serialize_length_and_data_to(&mut w);
+        assert_eq!(w.into_bytes(), bytes[..4]);
+    }
+
+    #[test]
+    fn it_rejects_unknown_type_overrunning_message() {
+        let bytes = [0, 4, 0, 10];
+        assert_eq!(
+            RData::deserialize(&bytes, RecordType::Unknown(99), &bytes),
+            Err(DnsError::UnexpectedEnd {
+                offset: 2,
+                needed: 2
+            })
+        );
+    }
+
+    #[test]
+    fn it_displays_presentation_format() {
+        assert_eq!(
+            RData::Unknown(99, vec![0x0a, 0, 0, 1]).to_string(),
+            "\\# 4 0a000001"
+        );
+        assert_eq!(RData::Unknown(99, vec![]).to_string(), "\\# 0");
+        assert_eq!(
+            RData::Mx(Mx {
+                preference: 10,
+                exchange: LabelSeq::_new("smtp.google.com")
+            })
+            .to_string(),
+            "10 smtp.google.com."
+        );
+        assert_eq!(
+            RData::Txt(Txt {
+                strings: vec![b"say \"hi\"".to_vec(), vec![0x07]]
+            })
+            .to_string(),
+            "\"say \\\"hi\\\"\" \"\\007\""
+        );
+    }
+}
@@ -1,25 +1,140 @@
+use std::collections::HashMap;
+
+use crate::dns_error::DnsError;
+
 pub trait DnsSerialize {
-    fn serialize(&self) -> Vec<u8>;
+    /// Appends the wire form to `writer`, compressing names if the writer allows it.
+    fn serialize_to(&self, writer: &mut DnsWriter);
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut writer = DnsWriter::new();
+        self.serialize_to(&mut writer);
+        writer.into_bytes()
+    }
+}
+
+/// Compression pointers only have 14 bits for the offset they point to.
+const MAX_POINTER_OFFSET: usize = 0x3FFF;
+
+/// Buffer for a message being serialized. Remembers the offset of every name suffix written
+/// so far so that later occurrences can be replaced by a compression pointer.
+pub struct DnsWriter {
+    buf: Vec<u8>,
+    names: HashMap<String, u16>, // <name suffix, offset>
+    canonical: bool,
+}
+
+impl DnsWriter {
+    pub fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            names: HashMap::new(),
+            canonical: false,
+        }
+    }
+
+    /// A writer producing the canonical (DNSSEC) form: no compression and lowercase names.
+    pub fn _canonical() -> Self {
+        Self {
+            canonical: true,
+            ..Self::new()
+        }
+    }
+
+    pub fn is_canonical(&self) -> bool {
+        self.canonical
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        self.buf.push(byte);
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Overwrites two bytes previously written at `offset`, e.g. a length known only afterwards.
+    pub fn patch_u16(&mut self, offset: usize, value: u16) {
+        self.buf[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// Returns the offset of an earlier occurrence of `suffix` that a pointer can refer to.
+    pub fn compression_target(&self, suffix: &str) -> Option<u16> {
+        if self.canonical {
+            return None;
+        }
+        self.names.get(suffix).copied()
+    }
+
+    /// Records that `suffix` is about to be written at the current offset.
+    pub fn remember_name(&mut self, suffix: &str) {
+        if !self.canonical && self.buf.len() <= MAX_POINTER_OFFSET {
+            self.names
+                .entry(suffix.to_string())
+                .or_insert(self.buf.len() as u16);
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
 }
 
 pub trait DnsDeserialize: Sized {
-    fn deserialize(data: &[u8]) -> (&[u8], Self);
+    /// Deserializes from `data`, which must be a suffix of the whole message `msg`. The
+    /// message is needed to follow name compression pointers and to report absolute offsets.
+    fn deserialize_in<'a>(msg: &'a [u8], data: &'a [u8]) -> Result<(&'a [u8], Self), DnsError>;
+
+    fn deserialize(data: &[u8]) -> Result<(&[u8], Self), DnsError> {
+        Self::deserialize_in(data, data)
+    }
 
-    fn deserialize_multiple(data: &[u8], count: usize) -> (&[u8], Vec<Self>) {
+    fn deserialize_multiple_in<'a>(
+        msg: &'a [u8],
+        data: &'a [u8],
+        count: usize,
+    ) -> Result<(&'a [u8], Vec<Self>), DnsError> {
         let mut new_remainder = data;
         let mut items: Vec<Self> = Vec::new();
         for _ in 0..count {
-            let (remainder, item) = Self::deserialize(&new_remainder);
+            if new_remainder.is_empty() {
+                return Err(DnsError::CountMismatch {
+                    offset: offset_in(msg, new_remainder),
+                    expected: count,
+                    found: items.len(),
+                });
+            }
+            let (remainder, item) = Self::deserialize_in(msg, new_remainder)?;
             new_remainder = remainder;
             items.push(item);
         }
-        (new_remainder, items)
+        Ok((new_remainder, items))
     }
 }
 
+/// Returns the offset of `data` within `msg`, where `data` is a suffix of `msg`.
+pub fn offset_in(msg: &[u8], data: &[u8]) -> usize {
+    msg.len() - data.len()
+}
+
+/// Reads `N` bytes starting at `idx` in `data`, failing if the message is too short.
+pub fn bytes_at<const N: usize>(msg: &[u8], data: &[u8], idx: usize) -> Result<[u8; N], DnsError> {
+    data.get(idx..idx + N)
+        .map(|bytes| bytes.try_into().expect("slice should have N bytes"))
+        .ok_or_else(|| DnsError::UnexpectedEnd {
+            offset: offset_in(msg, data) + idx,
+            needed: idx + N - data.len(),
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
+        dns_error::DnsError,
         dns_question::DnsQuestion,
         dns_serde::{DnsDeserialize, DnsSerialize},
         label_seq::LabelSeq,
@@ -38,10 +153,28 @@ mod tests {
         let mut the_bytes = q1.serialize();
         the_bytes.extend_from_slice(&q2.serialize());
 
-        let (remainder, r) = DnsQuestion::deserialize_multiple(&the_bytes, 2);
+        let (remainder, r) =
+            DnsQuestion::deserialize_multiple_in(&the_bytes, &the_bytes, 2).unwrap();
         assert_eq!(r.len(), 2);
         assert_eq!(r[0], q1);
         assert_eq!(r[1], q2);
         assert_eq!(remainder.len(), 0);
     }
+
+    #[test]
+    fn it_reports_count_mismatch() {
+        let q1 = DnsQuestion {
+            name: LabelSeq::_new("codecrafters.io"),
+            ..Default::default()
+        };
+        let the_bytes = q1.serialize();
+        assert_eq!(
+            DnsQuestion::deserialize_multiple_in(&the_bytes, &the_bytes, 2),
+            Err(DnsError::CountMismatch {
+                offset: the_bytes.len(),
+                expected: 2,
+                found: 1
+            })
+        );
+    }
 }
@@ -0,0 +1,190 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream, UdpSocket},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::query_handler::{Client, QueryHandler, TruncatedAnswer};
+
+/// How long a client connection may stay silent before it is closed (RFC 7766 section 6.2.3).
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long writing a response may block before the connection is closed.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Most responses queued for a client that is slow to read them. Further responses are
+/// dropped, so the query handler never waits on a client.
+const MAX_QUEUED_RESPONSES: usize = 64;
+
+/// How long to wait on the resolver when retrying a truncated answer over TCP.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reads one message prefixed with its 2-byte length (RFC 1035 section 4.2.2).
+/// Returns `None` if the peer closed the connection before the next message.
+pub fn read_message<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 2];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut msg = vec![0; u16::from_be_bytes(len).into()];
+    reader.read_exact(&mut msg)?;
+    Ok(Some(msg))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, msg: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(msg.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too long for tcp"))?;
+    let mut framed = Vec::with_capacity(msg.len() + 2);
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(msg);
+    writer.write_all(&framed)
+}
+
+/// Reads queries from a client connection until it is closed or stays idle for too long.
+/// Queries may be pipelined; each response is written as soon as it is ready, so responses
+/// can come back in a different order (RFC 7766 section 6.2.1.1). Responses are written by
+/// their own thread, from a queue the query handler adds to.
+pub fn serve_connection(
+    stream: TcpStream,
+    query_handler: Arc<Mutex<QueryHandler>>,
+    resolver_addr: &str,
+    udp_socket: &UdpSocket,
+) {
+    let peer_addr = match stream.peer_addr() {
+        Ok(addr) => addr,
+        Err(e) => {
+            eprintln!("Error reading tcp peer address: {}", e);
+            return;
+        }
+    };
+    let timeouts = stream
+        .set_read_timeout(Some(IDLE_TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(WRITE_TIMEOUT)));
+    if let Err(e) = timeouts {
+        eprintln!("Error setting tcp timeout: {}", e);
+        return;
+    }
+    println!("accepted tcp connection from {}", peer_addr);
+    let (responses, queued) = mpsc::sync_channel(MAX_QUEUED_RESPONSES);
+    let writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(e) => {
+            eprintln!("Error cloning tcp stream: {}", e);
+            return;
+        }
+    };
+    let writer = thread::spawn(move || write_responses(writer, peer_addr, queued));
+    loop {
+        match read_message(&mut &stream) {
+            Ok(Some(query)) => {
+                println!("Received {} bytes from {} (tcp)", query.len(), peer_addr);
+                query_handler
+                    .lock()
+                    .expect("query handler lock should not be poisoned")
+                    .handle_query(
+                        &query,
+                        Client::Tcp(peer_addr, responses.clone()),
+                        Some((resolver_addr, udp_socket)),
+                    );
+            }
+            Ok(None) => {
+                // the client may still be waiting on responses to queries it already sent
+                println!("tcp connection from {} closed", peer_addr);
+                break;
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                println!("closing idle tcp connection from {}", peer_addr);
+                let _ = stream.shutdown(Shutdown::Both);
+                break;
+            }
+            Err(e) => {
+                eprintln!("Error reading from {}: {}", peer_addr, e);
+                break;
+            }
+        }
+    }
+    // the writer stops once the queries still pending have been answered
+    drop(responses);
+    let _ = writer.join();
+}
+
+/// Writes the responses queued for a client until every sender is gone. If the client stops
+/// reading, the connection is closed, which also ends the loop reading its queries.
+fn write_responses(mut stream: TcpStream, peer_addr: SocketAddr, queued: Receiver<Vec<u8>>) {
+    for response in queued {
+        if let Err(e) = write_message(&mut stream, &response) {
+            eprintln!("Error writing to {}: {}", peer_addr, e);
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+    }
+}
+
+/// Sends `query` to the resolver over a new TCP connection and returns the response.
+pub fn query_upstream(resolver: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect_timeout(&resolver, UPSTREAM_TIMEOUT)?;
+    stream.set_read_timeout(Some(UPSTREAM_TIMEOUT))?;
+    stream.set_write_timeout(Some(UPSTREAM_TIMEOUT))?;
+    write_message(&mut stream, query)?;
+    read_message(&mut stream)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "resolver closed the connection",
+        )
+    })
+}
+
+/// Asks the resolver again over TCP for an answer that came back truncated over UDP. The
+/// query handler is only locked once the resolver has responded or the exchange has failed.
+pub fn retry_truncated(
+    truncated: TruncatedAnswer,
+    query_handler: Arc<Mutex<QueryHandler>>,
+    udp_socket: &UdpSocket,
+) {
+    let response = query_upstream(truncated.resolver(), truncated.query_bytes());
+    query_handler
+        .lock()
+        .expect("query handler lock should not be poisoned")
+        .handle_tcp_answer(truncated, response, udp_socket);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn it_frames_pipelined_messages() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, &[1, 2, 3]).unwrap();
+        write_message(&mut buf, &[4]).unwrap();
+        assert_eq!(buf, [0, 3, 1, 2, 3, 0, 1, 4]);
+
+        let mut reader = Cursor::new(buf);
+        assert_eq!(read_message(&mut reader).unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(read_message(&mut reader).unwrap(), Some(vec![4]));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn it_rejects_truncated_message() {
+        let mut reader = Cursor::new([0, 5, 1, 2]);
+        assert_eq!(
+            read_message(&mut reader).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+}
@@ -1,79 +1,112 @@
-use crate::dns_serde::DnsSerialize;
+use std::fmt;
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum DnsType {
-    A(u8, u8, u8, u8),
-    _Cname,
+/// The TYPE field of questions and resource records. Records of `Unknown` types, like the
+/// DNSSEC ones, are passed on with their RDATA left as raw bytes.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Default)]
+pub enum RecordType {
+    #[default]
+    A,
+    Ns,
+    Cname,
+    Soa,
+    Ptr,
+    Mx,
+    Txt,
+    Aaaa,
+    Srv,
+    Opt,
+    Any,
+    Unknown(u16),
 }
 
-impl DnsType {
-    pub fn int_as_bytes(&self) -> [u8; 2] {
+impl RecordType {
+    pub fn code(&self) -> u16 {
         match self {
-            DnsType::A(..) => 1u16,
-            DnsType::_Cname => 5u16,
-        }
-        .to_be_bytes()
-    }
-
-    pub fn from_bytes(bytes: [u8; 2]) -> Self {
-        match u16::from_be_bytes(bytes) {
-            1 => DnsType::A(0, 0, 0, 0),
-            5 => DnsType::_Cname,
-            0_u16 | 2_u16..=4_u16 | 6_u16..=u16::MAX => DnsType::A(0, 0, 0, 0),
+            RecordType::A => 1,
+            RecordType::Ns => 2,
+            RecordType::Cname => 5,
+            RecordType::Soa => 6,
+            RecordType::Ptr => 12,
+            RecordType::Mx => 15,
+            RecordType::Txt => 16,
+            RecordType::Aaaa => 28,
+            RecordType::Srv => 33,
+            RecordType::Opt => 41,
+            RecordType::Any => 255,
+            RecordType::Unknown(code) => *code,
         }
     }
 
-    pub fn serialize_to_length_and_data(&self) -> Vec<u8> {
-        let mut s = match self {
-            DnsType::A(..) => 4u16,
-            DnsType::_Cname => todo!(),
-        }
-        .to_be_bytes()
-        .to_vec();
-        s.extend_from_slice(&self.serialize());
-        s
+    pub fn int_as_bytes(&self) -> [u8; 2] {
+        self.code().to_be_bytes()
     }
 
-    pub fn deserialize(type_bytes: [u8; 2], length_and_data_bytes: &[u8]) -> (&[u8], Self) {
-        match Self::from_bytes(type_bytes) {
-            DnsType::A(..) => Self::deserialize_a_type(length_and_data_bytes),
-            DnsType::_Cname => todo!(),
-        }
+    pub fn from_bytes(bytes: [u8; 2]) -> Self {
+        u16::from_be_bytes(bytes).into()
     }
 
-    fn deserialize_a_type(length_and_data_bytes: &[u8]) -> (&[u8], Self) {
-        assert_eq!(
-            u16::from_be_bytes(
-                length_and_data_bytes[..2]
-                    .try_into()
-                    .expect("Expected to find at least 2 bytes")
-            ),
-            4
-        );
-        (
-            &length_and_data_bytes[6..],
-            DnsType::A(
-                length_and_data_bytes[2],
-                length_and_data_bytes[3],
-                length_and_data_bytes[4],
-                length_and_data_bytes[5],
-            ),
-        )
+    /// Parses the mnemonic written by `Display`, ignoring case, or `TYPE<code>` (RFC 3597).
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        let record_type = match mnemonic.to_ascii_uppercase().as_str() {
+            "A" => RecordType::A,
+            "NS" => RecordType::Ns,
+            "CNAME" => RecordType::Cname,
+            "SOA" => RecordType::Soa,
+            "PTR" => RecordType::Ptr,
+            "MX" => RecordType::Mx,
+            "TXT" => RecordType::Txt,
+            "AAAA" => RecordType::Aaaa,
+            "SRV" => RecordType::Srv,
+            "OPT" => RecordType::Opt,
+            "ANY" => RecordType::Any,
+            other => {
+                let code = other.strip_prefix("TYPE")?;
+                if !code.starts_with(|c: char| c.is_ascii_digit()) {
+                    return None;
+                }
+                code.parse::<u16>().ok()?.into()
+            }
+        };
+        Some(record_type)
     }
 }
 
-impl DnsSerialize for DnsType {
-    fn serialize(&self) -> Vec<u8> {
-        match self {
-            DnsType::A(a, b, c, d) => vec![*a, *b, *c, *d],
-            DnsType::_Cname => todo!(),
+impl From<u16> for RecordType {
+    fn from(code: u16) -> Self {
+        match code {
+            1 => RecordType::A,
+            2 => RecordType::Ns,
+            5 => RecordType::Cname,
+            6 => RecordType::Soa,
+            12 => RecordType::Ptr,
+            15 => RecordType::Mx,
+            16 => RecordType::Txt,
+            28 => RecordType::Aaaa,
+            33 => RecordType::Srv,
+            41 => RecordType::Opt,
+            255 => RecordType::Any,
+            code => RecordType::Unknown(code),
         }
     }
 }
 
-impl Default for DnsType {
-    fn default() -> Self {
-        DnsType::A(0, 0, 0, 0)
+/// Mnemonic as used in master files, or `TYPE<code>` for unknown types (RFC 3597).
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordType::A => write!(f, "A"),
+            RecordType::Ns => write!(f, "NS"),
+            RecordType::Cname => write!(f, "CNAME"),
+            RecordType::Soa => write!(f, "SOA"),
+            RecordType::Ptr => write!(f, "PTR"),
+            RecordType::Mx => write!(f, "MX"),
+            RecordType::Txt => write!(f, "TXT"),
+            RecordType::Aaaa => write!(f, "AAAA"),
+            RecordType::Srv => write!(f, "SRV"),
+            RecordType::Opt => write!(f, "OPT"),
+            RecordType::Any => write!(f, "ANY"),
+            RecordType::Unknown(code) => write!(f, "TYPE{}", code),
+        }
     }
 }
 
@@ -82,20 +115,31 @@ mod tests {
     use super::*;
 
     #[test]
-    fn it_serdes() {
-        let t = DnsType::A(8, 8, 8, 8);
+    fn it_returns_correct_type_id() {
+        assert_eq!(RecordType::A.int_as_bytes(), [0, 1]);
+        assert_eq!(RecordType::Cname.int_as_bytes(), [0, 5]);
+        assert_eq!(RecordType::Aaaa.int_as_bytes(), [0, 28]);
+    }
 
-        let expected_bytes = [0, 4, 8, 8, 8, 8];
-        assert_eq!(t.serialize_to_length_and_data(), expected_bytes);
-        assert_eq!(
-            DnsType::deserialize(1u16.to_be_bytes(), &expected_bytes).1,
-            t
-        );
+    #[test]
+    fn it_round_trips_unknown_codes() {
+        let t = RecordType::from_bytes([0, 99]);
+        assert_eq!(t, RecordType::Unknown(99));
+        assert_eq!(t.int_as_bytes(), [0, 99]);
+        assert_eq!(RecordType::from(15), RecordType::Mx);
+        assert_eq!(t.to_string(), "TYPE99");
     }
 
     #[test]
-    fn it_returns_correct_type_id() {
-        assert_eq!(DnsType::A(8, 8, 8, 8).int_as_bytes(), [0, 1]);
-        assert_eq!(DnsType::_Cname.int_as_bytes(), [0, 5]);
+    fn it_parses_mnemonics() {
+        for code in [1, 2, 5, 6, 12, 15, 16, 28, 33, 41, 255, 99] {
+            let t = RecordType::from(code);
+            assert_eq!(RecordType::from_mnemonic(&t.to_string()), Some(t));
+        }
+        assert_eq!(RecordType::from_mnemonic("cname"), Some(RecordType::Cname));
+        assert_eq!(RecordType::from_mnemonic("TYPE1"), Some(RecordType::A));
+        assert_eq!(RecordType::from_mnemonic("TYPE+1"), None);
+        assert_eq!(RecordType::from_mnemonic("TYPE70000"), None);
+        assert_eq!(RecordType::from_mnemonic("IN"), None);
     }
 }
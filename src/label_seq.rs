@@ -1,4 +1,9 @@
-use crate::dns_serde::{DnsDeserialize, DnsSerialize};
+use std::fmt;
+
+use crate::{
+    dns_error::DnsError,
+    dns_serde::{bytes_at, offset_in, DnsDeserialize, DnsSerialize, DnsWriter},
+};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct LabelSeq {
@@ -9,52 +14,188 @@ impl LabelSeq {
     pub fn _new(name: &str) -> Self {
         Self { name: name.into() }
     }
+
+    /// Builds a name from its labels, or returns `None` if a label is empty, longer than 63
+    /// bytes or contains a dot, or if the name is longer than 255 bytes on the wire.
+    pub fn from_labels(labels: &[String]) -> Option<Self> {
+        let wire_len = labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1;
+        let valid_label = |label: &String| {
+            !label.is_empty() && label.len() <= MAX_LABEL_LEN as usize && !label.contains('.')
+        };
+        if wire_len > MAX_NAME_LEN || !labels.iter().all(valid_label) {
+            return None;
+        }
+        Some(Self {
+            name: labels.join("."),
+        })
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        self.name.split('.').filter(|label| !label.is_empty())
+    }
+
+    /// The name without its first label, or `None` for the root.
+    pub fn parent(&self) -> Option<Self> {
+        if self.name.is_empty() {
+            return None;
+        }
+        let name = self.name.split_once('.').map_or("", |(_, parent)| parent);
+        Some(Self { name: name.into() })
+    }
+
+    /// Whether this name is `ancestor` or below it, comparing case-insensitively.
+    pub fn is_subdomain_of(&self, ancestor: &LabelSeq) -> bool {
+        let mut name = Some(self.clone());
+        while let Some(current) = name {
+            if current.eq_ignore_case(ancestor) {
+                return true;
+            }
+            name = current.parent();
+        }
+        false
+    }
+
+    /// Names are compared case-insensitively on the wire, unlike `PartialEq`.
+    pub fn eq_ignore_case(&self, other: &LabelSeq) -> bool {
+        self.name.eq_ignore_ascii_case(&other.name)
+    }
+
+    /// Writes the name without pointers, for record types whose RDATA must not be compressed
+    /// (RFC 3597 section 4). The name can still be the target of later pointers.
+    pub fn serialize_uncompressed_to(&self, writer: &mut DnsWriter) {
+        self.write_labels(writer, false);
+    }
+
+    fn write_labels(&self, writer: &mut DnsWriter, compress: bool) {
+        let name = if writer.is_canonical() {
+            self.name.to_lowercase()
+        } else {
+            self.name.clone()
+        };
+        let labels: Vec<&str> = name.split('.').filter(|label| !label.is_empty()).collect();
+        for i in 0..labels.len() {
+            let suffix = labels[i..].join(".");
+            if let Some(offset) = writer.compression_target(&suffix).filter(|_| compress) {
+                writer.extend(&(offset | (POINTER_MASK as u16) << 8).to_be_bytes());
+                return;
+            }
+            writer.remember_name(&suffix);
+            writer.push(
+                labels[i]
+                    .len()
+                    .try_into()
+                    .expect("label length should not be longer than 128 bytes"),
+            );
+            writer.extend(labels[i].as_bytes());
+        }
+
+        writer.push(0x0);
+    }
 }
 
 impl DnsSerialize for LabelSeq {
-    fn serialize(&self) -> Vec<u8> {
-        let mut v: Vec<u8> = Vec::new();
-        self.name
-            .split(".")
-            .into_iter()
-            .map(|label| label.as_bytes())
-            .for_each(|label_bytes| {
-                v.push(
-                    label_bytes
-                        .len()
-                        .try_into()
-                        .expect("label length should not be longer than 128 bytes"),
-                );
-                v.extend_from_slice(&label_bytes)
-            });
-
-        v.push(0x0);
-        v
+    fn serialize_to(&self, writer: &mut DnsWriter) {
+        self.write_labels(writer, true);
     }
 }
 
-/// Returns the number of bytes parsed, and the parsed label
-fn parse_label(data: &[u8]) -> (usize, String) {
-    let len = data[0] as usize;
-    String::from_utf8(data[1..=len].into())
-        .and_then(|mut label| {
-            let new_starting_idx = (len as usize) + 1;
-            if data[new_starting_idx] != 0x00 {
-                label.push_str(".");
-                let (bytes_read, next_segment) = parse_label(&data[new_starting_idx..]);
-                label.push_str(&next_segment);
-                Ok((bytes_read + len + 1, label)) // +1 for the byte that indicates length
-            } else {
-                Ok((len + 2, label)) // +1 for the byte that indicates length and +1 for the last null byte
+/// Labels are at most 63 bytes; the two high bits of the length byte are reserved.
+const MAX_LABEL_LEN: u8 = 63;
+
+/// A length byte with both high bits set is a pointer to a name elsewhere in the message.
+const POINTER_MASK: u8 = 0b11000000;
+
+/// Names are at most 255 bytes on the wire, including length bytes and the final null byte.
+const MAX_NAME_LEN: usize = 255;
+
+/// Parses the name starting at `start` in `msg`, following compression pointers.
+/// Returns the number of bytes the name occupies at `start`, and the parsed label
+fn parse_label(msg: &[u8], start: usize) -> Result<(usize, String), DnsError> {
+    let mut label = String::new();
+    let mut pos = start;
+    let mut wire_len = 0;
+    // each pointer must jump strictly before the previous one, which rules out loops
+    let mut pointer_limit = start;
+    let mut bytes_read = None;
+    loop {
+        let len = *msg.get(pos).ok_or(DnsError::UnexpectedEnd {
+            offset: pos,
+            needed: 1,
+        })?;
+        if len & POINTER_MASK == POINTER_MASK {
+            let [hi, lo] = bytes_at(msg, msg, pos)?;
+            let target = u16::from_be_bytes([hi & !POINTER_MASK, lo]) as usize;
+            if target >= pointer_limit {
+                return Err(DnsError::BadPointer {
+                    offset: pos,
+                    target,
+                });
             }
-        })
-        .expect("invalid data for label")
+            if bytes_read.is_none() {
+                bytes_read = Some(pos + 2 - start);
+            }
+            pointer_limit = target;
+            pos = target;
+            continue;
+        }
+        if len > MAX_LABEL_LEN {
+            return Err(DnsError::BadLabelLength { offset: pos, len });
+        }
+        wire_len += len as usize + 1;
+        if wire_len > MAX_NAME_LEN {
+            return Err(DnsError::NameTooLong { offset: start });
+        }
+        if len == 0 {
+            return Ok((bytes_read.unwrap_or_else(|| pos + 1 - start), label));
+        }
+
+        let segment_bytes =
+            msg.get(pos + 1..=pos + len as usize)
+                .ok_or_else(|| DnsError::UnexpectedEnd {
+                    offset: pos + 1,
+                    needed: pos + 1 + len as usize - msg.len(),
+                })?;
+        let segment = std::str::from_utf8(segment_bytes)
+            .map_err(|_| DnsError::InvalidUtf8 { offset: pos + 1 })?;
+        // labels are joined with dots, so a dot inside one would change the name
+        if segment.contains('.') {
+            return Err(DnsError::DotInLabel { offset: pos + 1 });
+        }
+        if !label.is_empty() {
+            label.push('.');
+        }
+        label.push_str(segment);
+        pos += len as usize + 1; // +1 for the byte that indicates length
+    }
 }
 
 impl DnsDeserialize for LabelSeq {
-    fn deserialize(data: &[u8]) -> (&[u8], Self) {
-        let (bytes_read, label) = parse_label(data);
-        (&data[bytes_read..], Self { name: label })
+    fn deserialize_in<'a>(msg: &'a [u8], data: &'a [u8]) -> Result<(&'a [u8], Self), DnsError> {
+        let (bytes_read, label) = parse_label(msg, offset_in(msg, data))?;
+        Ok((&data[bytes_read..], Self { name: label }))
+    }
+}
+
+/// Fully qualified, with the trailing dot. Characters with a meaning in master files are
+/// escaped, and bytes outside printable ASCII are written as `\DDD`.
+impl fmt::Display for LabelSeq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.name.is_empty() {
+            return write!(f, ".");
+        }
+        for label in self.labels() {
+            for byte in label.bytes() {
+                match byte {
+                    b'.' | b';' | b'(' | b')' | b'"' | b'\\' | b'@' | b'$' => {
+                        write!(f, "\\{}", byte as char)?
+                    }
+                    0x21..=0x7E => write!(f, "{}", byte as char)?,
+                    _ => write!(f, "\\{:03}", byte)?,
+                }
+            }
+            write!(f, ".")?;
+        }
+        Ok(())
     }
 }
 
@@ -75,16 +216,20 @@ mod tests {
             12, 99, 111, 100, 101, 99, 114, 97, 102, 116, 101, 114, 115, 2, 105, 111, 0,
         ];
         assert_eq!(l.serialize(), expected_bytes);
-        let (remainder, dl) = LabelSeq::deserialize(&expected_bytes);
+        let (remainder, dl) = LabelSeq::deserialize(&expected_bytes).unwrap();
         assert_eq!(dl, l);
         assert_eq!(remainder.len(), 0)
     }
 
     #[test]
     fn it_parses_label() {
-        let (bytes_read, label) = parse_label(&[
-            0x06, 0x67, 0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00,
-        ]);
+        let (bytes_read, label) = parse_label(
+            &[
+                0x06, 0x67, 0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00,
+            ],
+            0,
+        )
+        .unwrap();
         assert_eq!(bytes_read, 12);
         assert_eq!(label, "google.com");
     }
@@ -95,8 +240,181 @@ mod tests {
             12, 99, 111, 100, 101, 99, 114, 97, 102, 116, 101, 114, 115, 2, 105, 111, 0, 0, 1, 0,
             1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
         ];
-        let (bytes_read, label) = parse_label(&bytes);
+        let (bytes_read, label) = parse_label(&bytes, 0).unwrap();
         assert_eq!(bytes_read, 17);
         assert_eq!(label, "codecrafters.io");
     }
+
+    #[test]
+    fn it_rejects_malformed_labels() {
+        assert_eq!(
+            parse_label(&[6, 103, 111, 111], 0),
+            Err(DnsError::UnexpectedEnd {
+                offset: 1,
+                needed: 3
+            })
+        );
+        assert_eq!(
+            parse_label(&[3, 99, 111, 109, 0x80, 0], 0),
+            Err(DnsError::BadLabelLength {
+                offset: 4,
+                len: 0x80
+            })
+        );
+        assert_eq!(
+            parse_label(&[2, 0xff, 0xfe, 0], 0),
+            Err(DnsError::InvalidUtf8 { offset: 1 })
+        );
+    }
+
+    #[test]
+    fn it_rejects_dots_in_labels() {
+        // one label "evil.com", which must not read as the two labels of evil.com
+        let bytes = [8, 101, 118, 105, 108, 46, 99, 111, 109, 0];
+        assert_eq!(
+            parse_label(&bytes, 0),
+            Err(DnsError::DotInLabel { offset: 1 })
+        );
+        assert_eq!(
+            parse_label(&[3, 97, 98, 99, 3, 46, 111, 109, 0], 0),
+            Err(DnsError::DotInLabel { offset: 5 })
+        );
+    }
+
+    #[test]
+    fn it_serdes_root() {
+        let l = LabelSeq::default();
+        assert_eq!(l.serialize(), [0]);
+        assert_eq!(l.to_string(), ".");
+        let (remainder, dl) = LabelSeq::deserialize(&[0, 0, 1]).unwrap();
+        assert_eq!(dl, l);
+        assert_eq!(remainder, [0, 1]);
+    }
+
+    #[test]
+    fn it_walks_up_the_tree() {
+        let l = LabelSeq::_new("www.Example.com");
+        assert_eq!(l.labels().collect::<Vec<_>>(), ["www", "Example", "com"]);
+        assert_eq!(l.parent(), Some(LabelSeq::_new("Example.com")));
+        assert_eq!(LabelSeq::_new("com").parent(), Some(LabelSeq::default()));
+        assert_eq!(LabelSeq::default().parent(), None);
+        assert!(l.is_subdomain_of(&LabelSeq::_new("example.COM")));
+        assert!(l.is_subdomain_of(&l));
+        assert!(l.is_subdomain_of(&LabelSeq::default()));
+        assert!(!l.is_subdomain_of(&LabelSeq::_new("ww.example.com")));
+        assert!(!LabelSeq::_new("example.com").is_subdomain_of(&l));
+    }
+
+    #[test]
+    fn it_builds_names_from_labels() {
+        let labels = ["www".to_string(), "example".to_string(), "com".to_string()];
+        assert_eq!(
+            LabelSeq::from_labels(&labels),
+            Some(LabelSeq::_new("www.example.com"))
+        );
+        assert_eq!(LabelSeq::from_labels(&[]), Some(LabelSeq::default()));
+        assert_eq!(LabelSeq::from_labels(&["".to_string()]), None);
+        assert_eq!(LabelSeq::from_labels(&["a.b".to_string()]), None);
+        assert_eq!(LabelSeq::from_labels(&["a".repeat(64)]), None);
+        assert_eq!(LabelSeq::from_labels(&vec!["a".repeat(63); 4]), None);
+    }
+
+    #[test]
+    fn it_escapes_special_characters_in_display() {
+        assert_eq!(
+            LabelSeq::_new("www.example.com").to_string(),
+            "www.example.com."
+        );
+        assert_eq!(
+            LabelSeq::_new("a b;c.@.é").to_string(),
+            "a\\032b\\;c.\\@.\\195\\169."
+        );
+    }
+
+    #[test]
+    fn it_follows_compression_pointers() {
+        // "google.com" at offset 0, then "www" + pointer to 0, then a bare pointer to 12
+        let msg = [
+            6, 103, 111, 111, 103, 108, 101, 3, 99, 111, 109, 0, 3, 119, 119, 119, 0xC0, 0, 0xC0,
+            12, 0xAB,
+        ];
+        let (remainder, l) = LabelSeq::deserialize_in(&msg, &msg[12..]).unwrap();
+        assert_eq!(l, LabelSeq::_new("www.google.com"));
+        assert_eq!(remainder, [0xC0, 12, 0xAB]);
+        let (remainder, l) = LabelSeq::deserialize_in(&msg, remainder).unwrap();
+        assert_eq!(l, LabelSeq::_new("www.google.com"));
+        assert_eq!(remainder, [0xAB]);
+    }
+
+    #[test]
+    fn it_rejects_pointer_loops() {
+        // pointer to itself
+        assert_eq!(
+            parse_label(&[3, 99, 111, 109, 0xC0, 4], 4),
+            Err(DnsError::BadPointer {
+                offset: 4,
+                target: 4
+            })
+        );
+        // pointer back to a label that leads to the same pointer again
+        assert_eq!(
+            parse_label(&[3, 99, 111, 109, 0xC0, 0], 4),
+            Err(DnsError::BadPointer {
+                offset: 4,
+                target: 0
+            })
+        );
+        // forward pointer
+        assert_eq!(
+            parse_label(&[0xC0, 2, 0], 0),
+            Err(DnsError::BadPointer {
+                offset: 0,
+                target: 2
+            })
+        );
+    }
+
+    #[test]
+    fn it_rejects_names_longer_than_255_bytes() {
+        let mut bytes = Vec::new();
+        for _ in 0..5 {
+            bytes.push(63);
+            bytes.extend_from_slice(&[b'a'; 63]);
+        }
+        bytes.push(0);
+        assert_eq!(
+            parse_label(&bytes, 0),
+            Err(DnsError::NameTooLong { offset: 0 })
+        );
+    }
+
+    #[test]
+    fn it_compresses_repeated_suffixes() {
+        let mut w = DnsWriter::new();
+        LabelSeq::_new("google.com").serialize_to(&mut w);
+        LabelSeq::_new("www.google.com").serialize_to(&mut w);
+        LabelSeq::_new("com").serialize_to(&mut w);
+        let bytes = w.into_bytes();
+        assert_eq!(
+            bytes,
+            [
+                6, 103, 111, 111, 103, 108, 101, 3, 99, 111, 109, 0, 3, 119, 119, 119, 0xC0, 0,
+                0xC0, 7
+            ]
+        );
+        let (remainder, l) = LabelSeq::deserialize_in(&bytes, &bytes[12..]).unwrap();
+        assert_eq!(l, LabelSeq::_new("www.google.com"));
+        let (_, l) = LabelSeq::deserialize_in(&bytes, remainder).unwrap();
+        assert_eq!(l, LabelSeq::_new("com"));
+    }
+
+    #[test]
+    fn it_does_not_compress_canonical_form() {
+        let mut w = DnsWriter::_canonical();
+        LabelSeq::_new("Google.com").serialize_to(&mut w);
+        LabelSeq::_new("google.com").serialize_to(&mut w);
+        let mut expected = LabelSeq::_new("google.com").serialize();
+        expected.extend_from_slice(&LabelSeq::_new("google.com").serialize());
+        assert_eq!(w.into_bytes(), expected);
+    }
 }
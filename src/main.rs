@@ -1,36 +1,185 @@
 mod dns_answer;
+mod dns_cache;
+mod dns_edns;
+mod dns_error;
 mod dns_header;
+mod dns_opcode;
 mod dns_packet;
 mod dns_question;
+mod dns_rcode;
+mod dns_rdata;
 mod dns_serde;
+mod dns_tcp;
 mod dns_type;
 mod label_seq;
 mod query_handler;
+mod upstream_sockets;
+mod zone;
+mod zone_file;
 
-use query_handler::QueryHandler;
-use std::{env, net::UdpSocket};
+use dns_cache::{DnsCache, PrefetchFraction, DEFAULT_CACHE_SIZE, DEFAULT_PREFETCH_FRACTION};
+use dns_edns::MAX_UDP_PAYLOAD;
+use query_handler::{Client, QueryHandler, RetryPolicy};
+use std::{
+    env, fs, io,
+    net::{TcpListener, UdpSocket},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use upstream_sockets::UpstreamSockets;
+use zone::Zone;
+
+/// How often pending queries are checked for missed deadlines.
+const EXPIRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returns the value following `flag` on the command line, if any.
+fn flag_value<'a>(args: &'a [String], flag: &'a str) -> Option<&'a str> {
+    flag_values(args, flag).next()
+}
+
+/// Returns the values following each occurrence of `flag` on the command line.
+fn flag_values<'a>(args: &'a [String], flag: &'a str) -> impl Iterator<Item = &'a str> {
+    args.windows(2)
+        .filter(move |pair| pair[0] == flag)
+        .map(|pair| pair[1].as_str())
+}
 
 fn main() {
     // You can use print statements as follows for debugging, they'll be visible when running tests.
     println!("Logs from your program will appear here!");
     let args: Vec<String> = env::args().collect();
-    let resolver_addr = &args[2];
+    let resolver_addr = args[2].clone();
     println!("resolver address: {}", resolver_addr);
+    let mut retry_policy = RetryPolicy::default();
+    if let Some(timeout_ms) = flag_value(&args, "--timeout-ms") {
+        retry_policy.timeout =
+            Duration::from_millis(timeout_ms.parse().expect("--timeout-ms should be a number"));
+    }
+    if let Some(retries) = flag_value(&args, "--retries") {
+        retry_policy.retries = retries.parse().expect("--retries should be a number");
+    }
+    let cache_size = flag_value(&args, "--cache-size").map_or(DEFAULT_CACHE_SIZE, |size| {
+        size.parse().expect("--cache-size should be a number")
+    });
+    let prefetch_fraction =
+        flag_value(&args, "--prefetch-fraction").map_or(DEFAULT_PREFETCH_FRACTION, |fraction| {
+            fraction
+                .parse()
+                .ok()
+                .and_then(PrefetchFraction::new)
+                .expect("--prefetch-fraction should be a number from 0 to 1")
+        });
+    let zones: Vec<Zone> = flag_values(&args, "--zone")
+        .map(|path| {
+            let text = fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("Failed to read zone file {}: {}", path, e));
+            let zone = Zone::from_master_file(&text, None)
+                .unwrap_or_else(|e| panic!("Failed to load zone file {}: {}", path, e));
+            println!("serving zone {} from {}", zone.origin(), path);
+            zone
+        })
+        .collect();
 
-    let udp_socket = UdpSocket::bind("127.0.0.1:2053").expect("Failed to bind to address");
-    let mut buf = [0; 512];
-    let mut query_handler = QueryHandler::new();
+    let udp_socket =
+        Arc::new(UdpSocket::bind("127.0.0.1:2053").expect("Failed to bind to address"));
+    udp_socket
+        .set_read_timeout(Some(EXPIRY_INTERVAL))
+        .expect("Failed to set socket timeout");
+    let tcp_listener = TcpListener::bind("127.0.0.1:2053").expect("Failed to bind to address");
+    let (upstream_sockets, upstream_messages) =
+        UpstreamSockets::new(&resolver_addr).expect("Failed to resolve resolver address");
+    let query_handler = Arc::new(Mutex::new(QueryHandler::new(
+        retry_policy,
+        upstream_sockets,
+        DnsCache::new(cache_size, prefetch_fraction),
+        zones,
+    )));
+
+    {
+        let query_handler = query_handler.clone();
+        let resolver_addr = resolver_addr.clone();
+        let udp_socket = udp_socket.clone();
+        thread::spawn(move || {
+            for message in upstream_messages {
+                println!(
+                    "Received {} bytes from {}",
+                    message.bytes.len(),
+                    message.source
+                );
+                let truncated = query_handler
+                    .lock()
+                    .expect("query handler lock should not be poisoned")
+                    .handle_answer(
+                        &message.bytes,
+                        message.source,
+                        message.socket_id,
+                        (&resolver_addr, &udp_socket),
+                    );
+                if let Some(truncated) = truncated {
+                    let query_handler = query_handler.clone();
+                    let udp_socket = udp_socket.clone();
+                    thread::spawn(move || {
+                        dns_tcp::retry_truncated(truncated, query_handler, &udp_socket)
+                    });
+                }
+            }
+        });
+    }
+
+    {
+        let query_handler = query_handler.clone();
+        let resolver_addr = resolver_addr.clone();
+        let udp_socket = udp_socket.clone();
+        thread::spawn(move || {
+            for stream in tcp_listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let query_handler = query_handler.clone();
+                        let resolver_addr = resolver_addr.clone();
+                        let udp_socket = udp_socket.clone();
+                        thread::spawn(move || {
+                            dns_tcp::serve_connection(
+                                stream,
+                                query_handler,
+                                &resolver_addr,
+                                &udp_socket,
+                            )
+                        });
+                    }
+                    Err(e) => eprintln!("Error accepting connection: {}", e),
+                }
+            }
+        });
+    }
 
+    let mut buf = [0; MAX_UDP_PAYLOAD as usize];
     loop {
         match udp_socket.recv_from(&mut buf) {
             Ok((size, source)) => {
                 println!("Received {} bytes from {}", size, source);
-                query_handler.handle_query(&buf, source, Some((&resolver_addr, &udp_socket)));
+                query_handler
+                    .lock()
+                    .expect("query handler lock should not be poisoned")
+                    .handle_query(
+                        &buf[..size],
+                        Client::Udp(source),
+                        Some((&resolver_addr, &udp_socket)),
+                    );
             }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
             Err(e) => {
                 eprintln!("Error receiving data: {}", e);
                 break;
             }
         }
+        query_handler
+            .lock()
+            .expect("query handler lock should not be poisoned")
+            .expire_pending(&resolver_addr, &udp_socket);
     }
 }
@@ -1,85 +1,1262 @@
 use std::{
     collections::HashMap,
+    fmt, io,
     net::{SocketAddr, UdpSocket},
+    sync::mpsc::{SyncSender, TrySendError},
+    time::{Duration, Instant},
 };
 
 use crate::{
+    dns_cache::{CachedResponse, DnsCache, DnssecFlags},
+    dns_edns::Edns,
+    dns_header::DnsHeader,
+    dns_opcode::Opcode,
     dns_packet::DnsPacket,
+    dns_question::DnsQuestion,
+    dns_rcode::Rcode,
     dns_serde::{DnsDeserialize, DnsSerialize},
+    upstream_sockets::{UpstreamSocket, UpstreamSockets},
+    zone::{self, Zone, ZoneAnswer},
 };
 
+/// Most questions forwarded to the resolver at once. Beyond this, clients are answered
+/// SERVFAIL rather than tying up more upstream ids and sockets.
+const MAX_UPSTREAM_QUERIES: usize = 512;
+
+/// How many random ids are tried for a forwarded question before giving up. With at most
+/// `MAX_UPSTREAM_QUERIES` ids in use, each try is free with a probability of at least 127/128.
+const UPSTREAM_ID_TRIES: usize = 16;
+
+/// Where a message came from, and where the response to it is sent.
+pub enum Client {
+    Udp(SocketAddr),
+    Tcp(SocketAddr, SyncSender<Vec<u8>>), // responses are queued for the connection's writer
+}
+
+impl Client {
+    fn addr(&self) -> SocketAddr {
+        match self {
+            Client::Udp(addr) | Client::Tcp(addr, _) => *addr,
+        }
+    }
+
+    /// Largest response the client accepts for `query`. Only UDP responses are truncated.
+    fn max_response_len(&self, query: &DnsPacket) -> usize {
+        match self {
+            Client::Udp(_) => query.max_udp_response_len(),
+            Client::Tcp(..) => u16::MAX.into(),
+        }
+    }
+
+    fn send(&self, socket: &UdpSocket, bytes: &[u8]) {
+        let result = match self {
+            Client::Udp(addr) => socket.send_to(bytes, addr).map(|_| ()),
+            // never waits on the client, a response that doesn't fit in the queue is dropped
+            Client::Tcp(_, responses) => responses.try_send(bytes.to_vec()).map_err(|e| match e {
+                TrySendError::Full(_) => {
+                    io::Error::new(io::ErrorKind::WouldBlock, "too many responses queued")
+                }
+                TrySendError::Disconnected(_) => {
+                    io::Error::new(io::ErrorKind::BrokenPipe, "connection closed")
+                }
+            }),
+        };
+        if let Err(e) = result {
+            println!("failed to respond to {}: {}", self, e);
+        }
+    }
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Client::Udp(addr) => write!(f, "{}", addr),
+            Client::Tcp(addr, _) => write!(f, "{} (tcp)", addr),
+        }
+    }
+}
+
+/// A client query waiting for the resolver to answer each of its questions.
+struct PendingQuery {
+    client: Client,
+    packet: DnsPacket,
+    answered: Vec<bool>, // indexed by question
+    rcode: Rcode,        // first error rcode from the resolver, like NXDOMAIN, if any
+}
+
+impl PendingQuery {
+    fn add_cached(&mut self, question_index: usize, cached: CachedResponse) {
+        for answer in cached.answers {
+            self.packet.add_answer(answer);
+        }
+        for authority in cached.authorities {
+            self.packet.add_authority(authority);
+        }
+        if self.rcode == Rcode::NoError {
+            self.rcode = cached.rcode;
+        }
+        // the cache doesn't remember whether the answer was authenticated
+        self.packet.header.ad = false;
+        self.answered[question_index] = true;
+    }
+
+    fn add_from_zone(&mut self, question_index: usize, answer: ZoneAnswer) {
+        for record in answer.answers {
+            self.packet.add_answer(record);
+        }
+        for authority in answer.authorities {
+            self.packet.add_authority(authority);
+        }
+        for additional in answer.additionals {
+            self.packet.add_additional(additional);
+        }
+        if self.rcode == Rcode::NoError {
+            self.rcode = answer.rcode;
+        }
+        self.packet.header.ad = false;
+        self.answered[question_index] = true;
+    }
+
+    /// Sends the response once every question has been answered.
+    fn respond(mut self, socket: &UdpSocket) {
+        let limit = self.client.max_response_len(&self.packet);
+        self.packet.prepare_for_response(true, self.rcode);
+        // the packet is the client's query, so it still carries the client's id
+        let resolved_bytes = self.packet.serialize_within(limit);
+        self.client.send(socket, &resolved_bytes);
+    }
+}
+
+/// How long to wait for the resolver before retransmitting a question, and how many times
+/// to retransmit it before answering SERVFAIL.
+pub struct RetryPolicy {
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(2),
+            retries: 2,
+        }
+    }
+}
+
+/// The question of a pending query that an upstream question answers.
+#[derive(Clone, Copy)]
+struct ClientQuestion {
+    client_addr: SocketAddr,
+    client_id: u16,
+    question_index: usize,
+}
+
+impl ClientQuestion {
+    fn pending_key(&self) -> (SocketAddr, u16) {
+        (self.client_addr, self.client_id)
+    }
+}
+
+/// A question forwarded to the resolver under its own id, so answers can't be confused
+/// between clients that picked the same id.
+struct UpstreamQuery {
+    for_client: Option<ClientQuestion>, // None when refreshing a cache entry
+    question: DnsQuestion,
+    dnssec: DnssecFlags,    // the client's, which the answer is cached under
+    socket: UpstreamSocket, // opened for this question, answers must arrive on it
+    query_bytes: Vec<u8>,   // kept for retransmission
+    deadline: Instant,
+    retries_left: u32,
+}
+
+/// An answer the resolver truncated over UDP. The question is asked again over TCP without
+/// holding up the handler, and the outcome is passed to [`QueryHandler::handle_tcp_answer`].
+pub struct TruncatedAnswer {
+    upstream_id: u16,
+    upstream: UpstreamQuery,
+    resolver: SocketAddr, // the address checked at startup, for both transports
+}
+
+impl TruncatedAnswer {
+    pub fn resolver(&self) -> SocketAddr {
+        self.resolver
+    }
+
+    /// The query that was sent over UDP, to send again over TCP.
+    pub fn query_bytes(&self) -> &[u8] {
+        &self.upstream.query_bytes
+    }
+}
+
 pub struct QueryHandler {
-    pending_queries: HashMap<u16, (SocketAddr, DnsPacket)>, // <packet_id, (source_addr, packet)>
+    retry_policy: RetryPolicy,
+    upstream_sockets: UpstreamSockets,
+    cache: DnsCache,
+    zones: Vec<Zone>, // answered from, before the cache and the resolver
+    // <(client addr, client id), pending query>
+    pending_queries: HashMap<(SocketAddr, u16), PendingQuery>,
+    upstream_queries: HashMap<u16, UpstreamQuery>, // <upstream id, forwarded question>
+    max_upstream_queries: usize,                   // MAX_UPSTREAM_QUERIES, lowered in tests
 }
 
 impl QueryHandler {
-    pub fn new() -> Self {
+    pub fn new(
+        retry_policy: RetryPolicy,
+        upstream_sockets: UpstreamSockets,
+        cache: DnsCache,
+        zones: Vec<Zone>,
+    ) -> Self {
         Self {
+            retry_policy,
+            upstream_sockets,
+            cache,
+            zones,
             pending_queries: HashMap::new(),
+            upstream_queries: HashMap::new(),
+            max_upstream_queries: MAX_UPSTREAM_QUERIES,
         }
     }
+
     pub fn handle_query(
         &mut self,
-        query_bytes: &[u8; 512],
-        source_addr: SocketAddr,
+        query_bytes: &[u8],
+        client: Client,
         resolver: Option<(&str, &UdpSocket)>,
     ) {
         if let Some((resolver_addr, socket)) = resolver {
-            let query_packet = DnsPacket::deserialize(query_bytes).1;
-            if query_packet.header.qr == 0 {
-                // query is a question
-                println!(
-                    "handling {} questions from {}",
-                    query_packet.header.qdcount, source_addr
-                );
-                println!("query packet: {:?}", query_packet);
-                let mut pending_query = query_packet.clone();
-                if query_packet.header.opcode != 0 {
-                    // this is not implemented yet
-                    pending_query.prepare_for_response(1);
-                    let r_bytes = pending_query.serialize();
-                    socket
-                        .send_to(&r_bytes, source_addr)
-                        .expect("Failed to forward query");
+            let query_packet = match DnsPacket::deserialize(query_bytes) {
+                Ok((_, packet)) => packet,
+                Err(e) => {
+                    println!("failed to parse packet from {}: {}", client, e);
+                    let is_query = !matches!(query_bytes.get(2), Some(b) if b >> 7 == 1);
+                    if is_query {
+                        let r_bytes = DnsPacket::format_error(query_bytes).serialize();
+                        client.send(socket, &r_bytes);
+                    }
                     return;
                 }
-                self.pending_queries
-                    .insert(query_packet.header.id, (source_addr, pending_query));
-                for question in query_packet.questions {
-                    let forward_header = query_packet.header.clone();
-                    let mut forward_packet = DnsPacket::new(forward_header, vec![question], None);
-                    forward_packet.prepare_for_response(0);
-                    let forward_bytes = forward_packet.serialize();
-                    println!("forwarding question to {}", resolver_addr);
-                    println!("{:?}", forward_packet);
-                    socket
-                        .send_to(&forward_bytes, resolver_addr)
-                        .expect("Failed to forward query");
+            };
+            if query_packet.header.qr {
+                // answers from the resolver arrive on the upstream sockets
+                println!("ignoring response from {}", client);
+                return;
+            }
+            // query is a question
+            println!(
+                "handling {} questions from {}",
+                query_packet.header.qdcount, client
+            );
+            println!("query packet: {:?}", query_packet);
+            // the response starts from the query, without any records the client sent along
+            let mut pending_query = query_packet.clone();
+            pending_query.answers = Some(Vec::new());
+            pending_query.authorities.clear();
+            pending_query.additionals.clear();
+            if query_packet.header.opcode != Opcode::Query {
+                // this is not implemented yet
+                pending_query.prepare_for_response(true, Rcode::NotImp);
+                let r_bytes = pending_query.serialize();
+                client.send(socket, &r_bytes);
+                return;
+            }
+            if matches!(&query_packet.edns, Some(edns) if edns.version != 0) {
+                // only EDNS version 0 is supported, answer BADVERS (RFC 6891 section 6.1.3)
+                pending_query.prepare_for_response(true, Rcode::BadVers);
+                let r_bytes = pending_query.serialize();
+                client.send(socket, &r_bytes);
+                return;
+            }
+            let dnssec = dnssec_flags(&query_packet);
+            let client_addr = client.addr();
+            let client_id = query_packet.header.id;
+            if self.pending_queries.contains_key(&(client_addr, client_id)) {
+                println!("query {} from {} is already in progress", client_id, client);
+                return;
+            }
+            let mut pending_query = PendingQuery {
+                client,
+                answered: vec![false; query_packet.questions.len()],
+                packet: pending_query,
+                rcode: Rcode::NoError,
+            };
+            let now = Instant::now();
+            let mut unanswered = Vec::new();
+            // the AA bit is only set if every question is answered from our zones
+            let mut authoritative = !query_packet.questions.is_empty();
+            for (question_index, question) in query_packet.questions.into_iter().enumerate() {
+                if let Some(zone) = zone::zone_for(&self.zones, &question.name) {
+                    println!(
+                        "answering question {} from zone {}",
+                        question_index,
+                        zone.origin()
+                    );
+                    let answer = zone.lookup(&question);
+                    authoritative &= answer.authoritative;
+                    pending_query.add_from_zone(question_index, answer);
+                    continue;
                 }
-            } else {
-                // is answer from resolver
-                println!("handling answer from {}", source_addr);
-                let (header, _, answers) = DnsPacket::deserialize(query_bytes).1.into_parts();
-
-                if let Some(ref mut pending_query) = self.pending_queries.get_mut(&header.id) {
-                    println!("found pending query with id {}", header.id);
-                    if let Some(answers) = answers {
-                        if answers.len() > 0 {
-                            println!("adding answer to pending query from {}", pending_query.0);
-                            pending_query.1.add_answer(answers[0].clone());
-                            if pending_query.1.all_questions_answered() {
-                                pending_query.1.prepare_for_response(1);
-                                let resolved_bytes = pending_query.1.serialize();
-                                socket
-                                    .send_to(&resolved_bytes, pending_query.0)
-                                    .expect("Failed to respond to query");
+                authoritative = false;
+                match self.cache.get(&question, dnssec, now) {
+                    Some(cached) => {
+                        println!("answering question {} from cache", question_index);
+                        if cached.prefetch {
+                            println!("refreshing cached answer to question {}", question_index);
+                            let header = &query_packet.header;
+                            if let Err(e) =
+                                self.forward(question, None, header, dnssec, resolver_addr)
+                            {
+                                println!("failed to refresh cached answer: {}", e);
                             }
-                        } else {
-                            println!("no answers were found, packet header: {:?}", header);
                         }
+                        pending_query.add_cached(question_index, cached);
                     }
+                    None => unanswered.push((question_index, question)),
+                }
+            }
+            pending_query.packet.header.aa = authoritative;
+            if unanswered.is_empty() {
+                pending_query.respond(socket);
+                return;
+            }
+            self.pending_queries
+                .insert((client_addr, client_id), pending_query);
+            for (question_index, question) in unanswered {
+                let for_client = ClientQuestion {
+                    client_addr,
+                    client_id,
+                    question_index,
+                };
+                let header = &query_packet.header;
+                if let Err(e) =
+                    self.forward(question, Some(for_client), header, dnssec, resolver_addr)
+                {
+                    println!(
+                        "failed to forward query {} from {}: {}",
+                        client_id, client_addr, e
+                    );
+                    self.fail_pending((client_addr, client_id), socket);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Sends `question` to the resolver under a new id, with the flags of the client query
+    /// in `header`. The question is only in flight if it could be sent, which it can't be
+    /// while too many others are.
+    fn forward(
+        &mut self,
+        question: DnsQuestion,
+        for_client: Option<ClientQuestion>,
+        header: &DnsHeader,
+        dnssec: DnssecFlags,
+        resolver_addr: &str,
+    ) -> io::Result<()> {
+        if self.upstream_queries.len() >= self.max_upstream_queries {
+            return Err(io::Error::other("too many questions in flight"));
+        }
+        let upstream_id = unused_id(&self.upstream_queries)
+            .ok_or_else(|| io::Error::other("no unused upstream id"))?;
+        let forward_header = DnsHeader {
+            id: upstream_id,
+            ..header.clone()
+        };
+        let forward_packet = forward_packet(forward_header, question.clone(), dnssec.dnssec_ok);
+        let forward_bytes = forward_packet.serialize();
+        let socket = self.upstream_sockets.open()?;
+        println!(
+            "forwarding question to {} from upstream socket {}",
+            resolver_addr,
+            socket.id()
+        );
+        println!("{:?}", forward_packet);
+        self.upstream_sockets.send(&socket, &forward_bytes)?;
+        self.upstream_queries.insert(
+            upstream_id,
+            UpstreamQuery {
+                for_client,
+                question,
+                dnssec,
+                socket,
+                query_bytes: forward_bytes,
+                deadline: Instant::now() + self.retry_policy.timeout,
+                retries_left: self.retry_policy.retries,
+            },
+        );
+        Ok(())
+    }
+
+    /// Handles an answer from the resolver that arrived on upstream socket `socket_id`.
+    /// Returns the question if the answer was truncated, to be retried over TCP.
+    pub fn handle_answer(
+        &mut self,
+        answer_bytes: &[u8],
+        source_addr: SocketAddr,
+        socket_id: u64,
+        resolver: (&str, &UdpSocket),
+    ) -> Option<TruncatedAnswer> {
+        let (resolver_addr, socket) = resolver;
+        let answer_packet = match DnsPacket::deserialize(answer_bytes) {
+            Ok((_, packet)) => packet,
+            Err(e) => {
+                println!("failed to parse answer from {}: {}", source_addr, e);
+                return None;
+            }
+        };
+        println!("handling answer from {}", source_addr);
+        let upstream_id = answer_packet.header.id;
+        let Some(upstream) = self.upstream_queries.get(&upstream_id) else {
+            println!("no question in flight with id {}", upstream_id);
+            return None;
+        };
+        // anyone can send a packet with a guessed id, so the answer is only accepted
+        // from the resolver, on the socket the question was sent from, and for the
+        // question that was asked
+        let resolver = self.upstream_sockets.resolver();
+        if !is_resolver(source_addr, resolver) || upstream.socket.id() != socket_id {
+            println!(
+                "dropping answer from {} on upstream socket {}, expected the resolver on {}",
+                source_addr,
+                socket_id,
+                upstream.socket.id()
+            );
+            return None;
+        }
+        if !echoes_question(&answer_packet, &upstream.question) {
+            println!(
+                "dropping answer with id {} for a question that was not asked: {:?}",
+                upstream_id, answer_packet.questions
+            );
+            return None;
+        }
+        let upstream = self.upstream_queries.remove(&upstream_id)?;
+        if answer_packet.header.tc {
+            println!(
+                "answer was truncated, retrying over tcp to {}",
+                resolver_addr
+            );
+            return Some(TruncatedAnswer {
+                upstream_id,
+                upstream,
+                resolver,
+            });
+        }
+        self.add_answer(upstream, answer_packet, socket);
+        None
+    }
+
+    /// Handles the resolver's response to a truncated answer retried over TCP. If the retry
+    /// failed, the question is given up on like one the resolver never answered.
+    pub fn handle_tcp_answer(
+        &mut self,
+        truncated: TruncatedAnswer,
+        response: io::Result<Vec<u8>>,
+        socket: &UdpSocket,
+    ) {
+        let TruncatedAnswer {
+            upstream_id,
+            upstream,
+            ..
+        } = truncated;
+        let response = response.and_then(|bytes| {
+            DnsPacket::deserialize(&bytes)
+                .map(|(_, packet)| packet)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        });
+        match response {
+            Ok(packet)
+                if packet.header.id == upstream_id
+                    && !packet.header.tc
+                    && echoes_question(&packet, &upstream.question) =>
+            {
+                self.add_answer(upstream, packet, socket);
+                return;
+            }
+            Ok(_) => println!("tcp answer to {} does not match the query", upstream_id),
+            Err(e) => println!("tcp retry of {} failed: {}", upstream_id, e),
+        }
+        match upstream.for_client {
+            Some(for_client) => self.fail_pending(for_client.pending_key(), socket),
+            None => println!("could not refresh cached answer to {:?}", upstream.question),
+        }
+    }
+
+    /// Caches a complete answer from the resolver and adds it to the pending query it is for.
+    fn add_answer(
+        &mut self,
+        upstream: UpstreamQuery,
+        answer_packet: DnsPacket,
+        socket: &UdpSocket,
+    ) {
+        let header = &answer_packet.header;
+        // the extended bits of the resolver's RCODE are about our query, not the client's
+        let rcode = Rcode::from_parts(header.rcode, 0);
+        let answers = answer_packet.answers_for(&upstream.question.name);
+        let now = Instant::now();
+        match answer_packet.negative_soa() {
+            Some(soa) if answers.is_empty() => {
+                self.cache
+                    .insert_negative(&upstream.question, upstream.dnssec, rcode, soa, now)
+            }
+            _ if rcode == Rcode::NoError => {
+                self.cache
+                    .insert(&upstream.question, upstream.dnssec, answers.clone(), now)
+            }
+            _ => {}
+        }
+        let Some(for_client) = upstream.for_client else {
+            println!("refreshed cached answer to {:?}", upstream.question);
+            return;
+        };
+        let pending_key = for_client.pending_key();
+
+        if let Some(pending_query) = self.pending_queries.get_mut(&pending_key) {
+            println!(
+                "found pending query with id {} for question {}",
+                for_client.client_id, for_client.question_index
+            );
+            if !answers.is_empty() {
+                println!(
+                    "adding answer to pending query from {}",
+                    pending_query.client
+                );
+                for answer in answers {
+                    println!("  {}", answer);
+                    pending_query.packet.add_answer(answer);
+                }
+            } else {
+                // NXDOMAIN or NODATA: the SOA in the authority section is forwarded
+                // below so the client can cache the negative answer
+                println!("no answers were found, rcode {:?}", answer_packet.rcode());
+                if let Some(soa) = answer_packet.negative_soa() {
+                    println!("  {}", soa);
+                }
+            }
+            // kept even when there are answers, e.g. a CNAME to a name that doesn't exist
+            if pending_query.rcode == Rcode::NoError {
+                pending_query.rcode = rcode;
+            }
+            // AD is only set if the client asked for it and every answer was authenticated
+            pending_query.packet.header.ad &= header.ad;
+            for authority in &answer_packet.authorities {
+                pending_query.packet.add_authority(authority.clone());
+            }
+            for additional in &answer_packet.additionals {
+                pending_query.packet.add_additional(additional.clone());
+            }
+            if let Some(answered) = pending_query.answered.get_mut(for_client.question_index) {
+                *answered = true;
+            }
+            if pending_query.answered.iter().all(|&answered| answered) {
+                if let Some(pending_query) = self.pending_queries.remove(&pending_key) {
+                    pending_query.respond(socket);
                 }
             }
         }
     }
+
+    /// Retransmits questions the resolver has not answered in time, and gives up on the
+    /// clients whose questions have run out of retransmissions.
+    pub fn expire_pending(&mut self, resolver_addr: &str, socket: &UdpSocket) {
+        let now = Instant::now();
+        let mut failed = Vec::new();
+        let mut abandoned = Vec::new();
+        for (upstream_id, upstream) in self.upstream_queries.iter_mut() {
+            if upstream.deadline > now {
+                continue;
+            }
+            if upstream.retries_left > 0 {
+                println!(
+                    "no answer for question {} yet, retransmitting to {}",
+                    upstream_id, resolver_addr
+                );
+                match self
+                    .upstream_sockets
+                    .send(&upstream.socket, &upstream.query_bytes)
+                {
+                    Ok(()) => {
+                        upstream.retries_left -= 1;
+                        upstream.deadline = now + self.retry_policy.timeout;
+                        continue;
+                    }
+                    Err(e) => println!("failed to retransmit question {}: {}", upstream_id, e),
+                }
+            }
+            match upstream.for_client {
+                Some(for_client) => failed.push(for_client.pending_key()),
+                None => abandoned.push(*upstream_id),
+            }
+        }
+        for upstream_id in abandoned {
+            self.upstream_queries.remove(&upstream_id);
+        }
+        for pending_key in failed {
+            self.fail_pending(pending_key, socket);
+        }
+    }
+
+    /// Answers a pending query with stale cached data for the questions the resolver did not
+    /// answer (RFC 8767), or with SERVFAIL if there is none, and forgets its questions still
+    /// in flight.
+    fn fail_pending(&mut self, pending_key: (SocketAddr, u16), socket: &UdpSocket) {
+        self.upstream_queries.retain(|_, upstream| {
+            !matches!(upstream.for_client, Some(for_client) if for_client.pending_key() == pending_key)
+        });
+        let Some(mut pending_query) = self.pending_queries.remove(&pending_key) else {
+            return;
+        };
+        println!(
+            "resolver did not answer query {} from {}",
+            pending_key.1, pending_query.client
+        );
+        let now = Instant::now();
+        let dnssec = dnssec_flags(&pending_query.packet);
+        let stale: Option<Vec<(usize, CachedResponse)>> = pending_query
+            .packet
+            .questions
+            .iter()
+            .enumerate()
+            .filter(|(question_index, _)| !pending_query.answered[*question_index])
+            .map(|(question_index, question)| {
+                Some((question_index, self.cache.get_stale(question, dnssec, now)?))
+            })
+            .collect();
+        if let Some(stale) = stale {
+            println!("answering with stale data");
+            for (question_index, cached) in stale {
+                pending_query.add_cached(question_index, cached);
+            }
+            pending_query.respond(socket);
+            return;
+        }
+        // partial answers from the questions that did resolve are not sent
+        let packet = &mut pending_query.packet;
+        packet.answers = Some(Vec::new());
+        packet.authorities.clear();
+        packet.additionals.clear();
+        packet.prepare_for_response(true, Rcode::ServFail);
+        pending_query.client.send(socket, &packet.serialize());
+    }
+}
+
+/// Picks a random id that is not used by another question in flight, which also makes
+/// forged answers harder to get accepted. Returns `None` if none of the ids tried is free.
+fn unused_id<T>(in_flight: &HashMap<u16, T>) -> Option<u16> {
+    (0..UPSTREAM_ID_TRIES)
+        .map(|_| rand::random())
+        .find(|id| !in_flight.contains_key(id))
+}
+
+/// The flags of a client's query that the resolver's answer depends on.
+fn dnssec_flags(query: &DnsPacket) -> DnssecFlags {
+    DnssecFlags {
+        dnssec_ok: matches!(&query.edns, Some(edns) if edns.dnssec_ok()),
+        checking_disabled: query.header.cd,
+    }
+}
+
+/// Builds the single-question query sent to the resolver for one of a client's questions.
+fn forward_packet(header: DnsHeader, question: DnsQuestion, dnssec_ok: bool) -> DnsPacket {
+    let mut packet = DnsPacket::new(header, vec![question], None);
+    packet.edns = Some(Edns::for_query(dnssec_ok));
+    packet.prepare_for_response(false, Rcode::NoError);
+    packet
+}
+
+/// Whether `addr` is the resolver's address, also when either is an IPv4 address mapped to
+/// IPv6.
+fn is_resolver(addr: SocketAddr, resolver: SocketAddr) -> bool {
+    addr.ip().to_canonical() == resolver.ip().to_canonical() && addr.port() == resolver.port()
+}
+
+/// Whether `packet` is an answer to exactly `question`.
+fn echoes_question(packet: &DnsPacket, question: &DnsQuestion) -> bool {
+    matches!(packet.questions.as_slice(), [echoed] if echoed.matches(question))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, sync::mpsc};
+
+    use super::*;
+    use crate::{
+        dns_answer::DnsAnswer,
+        dns_cache::{DEFAULT_CACHE_SIZE, DEFAULT_PREFETCH_FRACTION},
+        dns_rdata::{RData, Soa},
+        dns_type::RecordType,
+        label_seq::LabelSeq,
+        upstream_sockets::UpstreamMessage,
+    };
+
+    const ZONE: &str = "\
+$ORIGIN example.com.
+$TTL 3600
+@       SOA     ns1 hostmaster 1 7200 900 1209600 300
+www     A       192.0.2.1
+";
+
+    /// A handler with a fake resolver and the sockets clients talk to it through.
+    struct Fixture {
+        handler: QueryHandler,
+        upstream_messages: mpsc::Receiver<UpstreamMessage>,
+        resolver: UdpSocket,
+        resolver_addr: String,
+        server: UdpSocket,
+        client: UdpSocket,
+    }
+
+    fn bind() -> UdpSocket {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        socket
+    }
+
+    impl Fixture {
+        fn new(retry_policy: RetryPolicy) -> Self {
+            let resolver = bind();
+            let resolver_addr = resolver.local_addr().unwrap().to_string();
+            let (upstream_sockets, upstream_messages) =
+                UpstreamSockets::new(&resolver_addr).unwrap();
+            let zones = vec![Zone::from_master_file(ZONE, None).unwrap()];
+            Self {
+                handler: QueryHandler::new(
+                    retry_policy,
+                    upstream_sockets,
+                    DnsCache::new(DEFAULT_CACHE_SIZE, DEFAULT_PREFETCH_FRACTION),
+                    zones,
+                ),
+                upstream_messages,
+                resolver,
+                resolver_addr,
+                server: bind(),
+                client: bind(),
+            }
+        }
+
+        fn query(&mut self, packet: &DnsPacket) {
+            let client = Client::Udp(self.client.local_addr().unwrap());
+            self.query_from(client, packet);
+        }
+
+        fn query_from(&mut self, client: Client, packet: &DnsPacket) {
+            let resolver = Some((self.resolver_addr.as_str(), &self.server));
+            self.handler
+                .handle_query(&packet.serialize(), client, resolver);
+        }
+
+        /// Reads a question sent to the resolver, with the address it was sent from.
+        fn forwarded(&self) -> (DnsPacket, SocketAddr) {
+            let mut buf = [0; 4096];
+            let (size, source) = self.resolver.recv_from(&mut buf).unwrap();
+            (DnsPacket::deserialize(&buf[..size]).unwrap().1, source)
+        }
+
+        /// Sends `packet` from the resolver to `upstream_addr` and handles it once it has
+        /// been read from the upstream socket.
+        fn answer(
+            &mut self,
+            packet: &DnsPacket,
+            upstream_addr: SocketAddr,
+        ) -> Option<TruncatedAnswer> {
+            self.resolver
+                .send_to(&packet.serialize(), upstream_addr)
+                .unwrap();
+            self.handle_upstream_message()
+        }
+
+        /// Handles the next message read from the upstream sockets.
+        fn handle_upstream_message(&mut self) -> Option<TruncatedAnswer> {
+            let message = self
+                .upstream_messages
+                .recv_timeout(Duration::from_secs(1))
+                .unwrap();
+            let resolver = (self.resolver_addr.as_str(), &self.server);
+            self.handler
+                .handle_answer(&message.bytes, message.source, message.socket_id, resolver)
+        }
+
+        fn expire_pending(&mut self) {
+            self.handler
+                .expire_pending(&self.resolver_addr, &self.server);
+        }
+
+        fn response(&self) -> DnsPacket {
+            response_on(&self.client)
+        }
+    }
+
+    fn response_on(client: &UdpSocket) -> DnsPacket {
+        let mut buf = [0; 4096];
+        let size = client.recv(&mut buf).unwrap();
+        DnsPacket::deserialize(&buf[..size]).unwrap().1
+    }
+
+    fn query(id: u16, names: &[&str]) -> DnsPacket {
+        let header = DnsHeader {
+            id,
+            rd: true,
+            ..Default::default()
+        };
+        let questions = names
+            .iter()
+            .map(|name| DnsQuestion {
+                name: LabelSeq::_new(name),
+                _type: RecordType::A,
+                _class: 1,
+            })
+            .collect();
+        DnsPacket::new(header, questions, None)
+    }
+
+    fn record(name: &str) -> DnsAnswer {
+        DnsAnswer {
+            name: LabelSeq::_new(name),
+            rdata: RData::A(Ipv4Addr::new(198, 51, 100, 1)),
+            _class: 1,
+            ttl: 60,
+        }
+    }
+
+    #[test]
+    fn it_does_not_echo_records_sent_by_the_client() {
+        let mut fixture = Fixture::new(RetryPolicy::default());
+        for opcode in [Opcode::Query, Opcode::Notify] {
+            let mut packet = query(7, &["www.example.com"]);
+            packet.header.opcode = opcode;
+            packet.answers = Some(vec![record("forged.example.com")]);
+            packet.authorities.push(record("forged.example.com"));
+            packet.additionals.push(record("forged.example.com"));
+            packet.prepare_for_response(false, Rcode::NoError);
+            fixture.query(&packet);
+
+            let response = fixture.response();
+            let answers = response.answers.unwrap();
+            assert!(answers
+                .iter()
+                .all(|a| a.name != LabelSeq::_new("forged.example.com")));
+            assert!(response.authorities.is_empty());
+            assert!(response.additionals.is_empty());
+        }
+    }
+
+    #[test]
+    fn it_does_not_wait_on_tcp_clients() {
+        let mut fixture = Fixture::new(RetryPolicy::default());
+        let (responses, queued) = mpsc::sync_channel(1);
+        let client_addr = fixture.client.local_addr().unwrap();
+        // a client that pipelines queries answered from the zone but never reads
+        for id in [7, 8] {
+            let client = Client::Tcp(client_addr, responses.clone());
+            fixture.query_from(client, &query(id, &["www.example.com"]));
+        }
+        let response = DnsPacket::deserialize(&queued.try_recv().unwrap())
+            .unwrap()
+            .1;
+        assert_eq!(response.header.id, 7);
+        assert!(queued.try_recv().is_err());
+    }
+
+    /// The resolver's answer to `forwarded`, with `answers` and the same id.
+    fn answer_to(forwarded: &DnsPacket, answers: Vec<DnsAnswer>) -> DnsPacket {
+        let mut answer = forwarded.clone();
+        answer.answers = Some(answers);
+        answer.prepare_for_response(true, Rcode::NoError);
+        answer
+    }
+
+    #[test]
+    fn it_fails_truncated_answers_that_cannot_be_retried() {
+        let mut fixture = Fixture::new(RetryPolicy::default());
+        fixture.query(&query(7, &["www.example.net"]));
+        let (forwarded, upstream_addr) = fixture.forwarded();
+        let mut answer = answer_to(&forwarded, vec![record("www.example.net")]);
+        answer.header.tc = true;
+
+        let truncated = fixture.answer(&answer, upstream_addr).unwrap();
+        assert_eq!(truncated.resolver(), fixture.resolver.local_addr().unwrap());
+        // the query is sent again as it was, not rebuilt from the truncated answer
+        assert_eq!(
+            DnsPacket::deserialize(truncated.query_bytes()).unwrap().1,
+            forwarded
+        );
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        fixture
+            .handler
+            .handle_tcp_answer(truncated, Err(refused), &fixture.server);
+        let response = fixture.response();
+        assert_eq!(response.header.id, 7);
+        assert_eq!(response.rcode(), Rcode::ServFail);
+        assert!(response.answers.unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_keeps_clients_with_the_same_id_apart() {
+        let mut fixture = Fixture::new(RetryPolicy::default());
+        let other_client = bind();
+        fixture.query(&query(7, &["a.example.net"]));
+        let other_addr = other_client.local_addr().unwrap();
+        fixture.query_from(Client::Udp(other_addr), &query(7, &["b.example.net"]));
+        let (first, first_addr) = fixture.forwarded();
+        let (second, second_addr) = fixture.forwarded();
+        assert_ne!(first.header.id, second.header.id);
+
+        // answered out of order
+        let answer = answer_to(&second, vec![record("b.example.net")]);
+        assert!(fixture.answer(&answer, second_addr).is_none());
+        let answer = answer_to(&first, vec![record("a.example.net")]);
+        assert!(fixture.answer(&answer, first_addr).is_none());
+
+        for (client, name) in [
+            (&fixture.client, "a.example.net"),
+            (&other_client, "b.example.net"),
+        ] {
+            let response = response_on(client);
+            assert_eq!(response.header.id, 7);
+            assert_eq!(response.answers.unwrap(), [record(name)]);
+        }
+        assert!(fixture.handler.pending_queries.is_empty());
+        assert!(fixture.handler.upstream_queries.is_empty());
+    }
+
+    #[test]
+    fn it_answers_repeated_questions_from_the_cache() {
+        let mut fixture = Fixture::new(RetryPolicy::default());
+        fixture.query(&query(7, &["www.example.net"]));
+        let (forwarded, upstream_addr) = fixture.forwarded();
+        let answer = answer_to(&forwarded, vec![record("www.example.net")]);
+        assert!(fixture.answer(&answer, upstream_addr).is_none());
+        fixture.response();
+
+        fixture.query(&query(8, &["WWW.example.net"]));
+        assert!(fixture.handler.upstream_queries.is_empty());
+        let response = fixture.response();
+        assert_eq!(response.header.id, 8);
+        assert_eq!(response.answers.unwrap(), [record("www.example.net")]);
+    }
+
+    /// An SOA record for `name`, as sent along with negative answers.
+    fn soa_record(name: &str) -> DnsAnswer {
+        DnsAnswer {
+            name: LabelSeq::_new(name),
+            rdata: RData::Soa(Soa {
+                mname: LabelSeq::_new("ns1.example.net"),
+                rname: LabelSeq::_new("hostmaster.example.net"),
+                serial: 1,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+            }),
+            _class: 1,
+            ttl: 3600,
+        }
+    }
+
+    /// The resolver's NXDOMAIN answer to `forwarded`, with the zone's SOA record.
+    fn nxdomain_to(forwarded: &DnsPacket) -> DnsPacket {
+        let mut answer = forwarded.clone();
+        answer.answers = Some(Vec::new());
+        answer.authorities.push(soa_record("example.net"));
+        answer.prepare_for_response(true, Rcode::NXDomain);
+        answer
+    }
+
+    #[test]
+    fn it_answers_any_type_from_a_cached_nxdomain() {
+        let mut fixture = Fixture::new(RetryPolicy::default());
+        fixture.query(&query(7, &["nx.example.net"]));
+        let (forwarded, upstream_addr) = fixture.forwarded();
+        assert!(fixture
+            .answer(&nxdomain_to(&forwarded), upstream_addr)
+            .is_none());
+        assert_eq!(fixture.response().rcode(), Rcode::NXDomain);
+
+        let mut aaaa = query(8, &["nx.example.net"]);
+        aaaa.questions[0]._type = RecordType::Aaaa;
+        fixture.query(&aaaa);
+        assert!(fixture.handler.upstream_queries.is_empty());
+        let response = fixture.response();
+        assert_eq!(response.header.id, 8);
+        assert_eq!(response.rcode(), Rcode::NXDomain);
+        assert!(response.answers.unwrap().is_empty());
+        // the SOA's TTL is capped at its MINIMUM field
+        let soa = DnsAnswer {
+            ttl: 300,
+            ..soa_record("example.net")
+        };
+        assert_eq!(response.authorities, [soa]);
+    }
+
+    /// Caches `records` as the answer to an A question for `name`, as of `age` ago.
+    fn cache_as_of(fixture: &mut Fixture, name: &str, records: Vec<DnsAnswer>, age: Duration) {
+        let question = &query(0, &[name]).questions[0];
+        let stored_at = Instant::now().checked_sub(age).unwrap();
+        let dnssec = DnssecFlags::default();
+        fixture
+            .handler
+            .cache
+            .insert(question, dnssec, records, stored_at);
+    }
+
+    #[test]
+    fn it_refreshes_popular_entries_near_expiry() {
+        let mut fixture = Fixture::new(RetryPolicy::default());
+        // 5 of 60 seconds left, within the default prefetch fraction
+        let records = vec![record("www.example.net")];
+        cache_as_of(
+            &mut fixture,
+            "www.example.net",
+            records,
+            Duration::from_secs(55),
+        );
+        fixture.query(&query(7, &["www.example.net"]));
+        assert_eq!(fixture.response().header.id, 7);
+        assert!(fixture.handler.upstream_queries.is_empty());
+
+        // hit again while near expiry, so it is worth refreshing
+        fixture.query(&query(8, &["www.example.net"]));
+        assert_eq!(fixture.response().header.id, 8);
+        assert!(fixture.handler.pending_queries.is_empty());
+        let (forwarded, _) = fixture.forwarded();
+        assert_eq!(
+            forwarded.questions,
+            query(8, &["www.example.net"]).questions
+        );
+        let upstream = &fixture.handler.upstream_queries[&forwarded.header.id];
+        assert!(upstream.for_client.is_none());
+    }
+
+    #[test]
+    fn it_answers_with_stale_data_when_the_resolver_does_not_answer() {
+        let retry_policy = RetryPolicy {
+            timeout: Duration::ZERO,
+            retries: 0,
+        };
+        let mut fixture = Fixture::new(retry_policy);
+        let records = vec![record("www.example.net")];
+        cache_as_of(
+            &mut fixture,
+            "www.example.net",
+            records,
+            Duration::from_secs(120),
+        );
+        fixture.query(&query(7, &["www.example.net"]));
+        fixture.forwarded();
+
+        fixture.expire_pending();
+        let response = fixture.response();
+        assert_eq!(response.header.id, 7);
+        assert_eq!(response.rcode(), Rcode::NoError);
+        // stale records get a short TTL (RFC 8767 section 4)
+        let stale = DnsAnswer {
+            ttl: 30,
+            ..record("www.example.net")
+        };
+        assert_eq!(response.answers.unwrap(), [stale]);
+        assert!(fixture.handler.pending_queries.is_empty());
+    }
+
+    #[test]
+    fn it_sets_aa_only_when_every_question_is_answered_from_a_zone() {
+        let mut fixture = Fixture::new(RetryPolicy::default());
+        fixture.query(&query(7, &["www.example.com", "nx.example.com"]));
+        let response = fixture.response();
+        assert!(response.header.aa);
+        assert_eq!(response.rcode(), Rcode::NXDomain);
+
+        fixture.query(&query(8, &["www.example.com", "www.example.net"]));
+        let (forwarded, upstream_addr) = fixture.forwarded();
+        let answer = answer_to(&forwarded, vec![record("www.example.net")]);
+        assert!(fixture.answer(&answer, upstream_addr).is_none());
+        let response = fixture.response();
+        assert_eq!(response.header.id, 8);
+        assert!(!response.header.aa);
+        assert_eq!(response.answers.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn it_merges_every_answer_and_keeps_nxdomain() {
+        let mut fixture = Fixture::new(RetryPolicy::default());
+        fixture.query(&query(7, &["a.example.net", "nx.example.net"]));
+        let (first, first_addr) = fixture.forwarded();
+        let (second, second_addr) = fixture.forwarded();
+        let (found, found_addr, missing, missing_addr) =
+            if first.questions[0].name == LabelSeq::_new("a.example.net") {
+                (first, first_addr, second, second_addr)
+            } else {
+                (second, second_addr, first, first_addr)
+            };
+
+        // a CNAME chain ending in several A records
+        let cname = DnsAnswer {
+            rdata: RData::Cname(LabelSeq::_new("b.example.net")),
+            ..record("a.example.net")
+        };
+        let other = DnsAnswer {
+            rdata: RData::A(Ipv4Addr::new(198, 51, 100, 2)),
+            ..record("b.example.net")
+        };
+        let records = vec![cname, record("b.example.net"), other];
+        let answer = answer_to(&found, records.clone());
+        assert!(fixture.answer(&answer, found_addr).is_none());
+        assert_eq!(fixture.handler.pending_queries.len(), 1);
+        assert!(fixture
+            .answer(&nxdomain_to(&missing), missing_addr)
+            .is_none());
+
+        let response = fixture.response();
+        assert_eq!(response.header.id, 7);
+        assert_eq!(response.rcode(), Rcode::NXDomain);
+        assert_eq!(response.answers.unwrap(), records);
+        assert_eq!(response.authorities, [soa_record("example.net")]);
+        assert!(fixture.handler.pending_queries.is_empty());
+    }
+
+    #[test]
+    fn it_drops_answers_from_elsewhere_or_on_the_wrong_socket() {
+        let mut fixture = Fixture::new(RetryPolicy::default());
+        fixture.query(&query(7, &["a.example.net", "b.example.net"]));
+        let (first, first_addr) = fixture.forwarded();
+        let (_, second_addr) = fixture.forwarded();
+        let answer = answer_to(&first, Vec::new());
+
+        // the right id and question, but not from the resolver
+        let forger = bind();
+        forger.send_to(&answer.serialize(), first_addr).unwrap();
+        assert!(fixture.handle_upstream_message().is_none());
+        // from the resolver, but on the socket of the other question
+        assert!(fixture.answer(&answer, second_addr).is_none());
+        assert_eq!(fixture.handler.upstream_queries.len(), 2);
+        assert_eq!(fixture.handler.pending_queries.len(), 1);
+
+        assert!(fixture.answer(&answer, first_addr).is_none());
+        assert_eq!(fixture.handler.upstream_queries.len(), 1);
+        assert!(!fixture
+            .handler
+            .upstream_queries
+            .contains_key(&first.header.id));
+    }
+
+    #[test]
+    fn it_ignores_repeated_queries_in_progress() {
+        let mut fixture = Fixture::new(RetryPolicy::default());
+        fixture.query(&query(7, &["www.example.net"]));
+        fixture.query(&query(7, &["www.example.net"]));
+        assert_eq!(fixture.handler.pending_queries.len(), 1);
+        assert_eq!(fixture.handler.upstream_queries.len(), 1);
+    }
+
+    #[test]
+    fn it_picks_upstream_ids_that_are_not_in_flight() {
+        // as many ids in use as there can be questions in flight
+        let in_flight: HashMap<u16, ()> = (0..=u16::MAX)
+            .step_by((1 << 16) / MAX_UPSTREAM_QUERIES)
+            .map(|id| (id, ()))
+            .collect();
+        assert_eq!(in_flight.len(), MAX_UPSTREAM_QUERIES);
+        let id = unused_id(&in_flight).unwrap();
+        assert!(!in_flight.contains_key(&id));
+
+        let in_flight: HashMap<u16, ()> = (0..=u16::MAX).map(|id| (id, ())).collect();
+        assert_eq!(unused_id(&in_flight), None);
+    }
+
+    #[test]
+    fn it_answers_servfail_when_too_many_questions_are_in_flight() {
+        let mut fixture = Fixture::new(RetryPolicy::default());
+        fixture.handler.max_upstream_queries = 2;
+        fixture.query(&query(7, &["a.example.net", "b.example.net"]));
+        let other_client = bind();
+        let other_addr = other_client.local_addr().unwrap();
+        fixture.query_from(Client::Udp(other_addr), &query(8, &["c.example.net"]));
+
+        let response = response_on(&other_client);
+        assert_eq!(response.header.id, 8);
+        assert_eq!(response.rcode(), Rcode::ServFail);
+        assert_eq!(fixture.handler.pending_queries.len(), 1);
+        assert_eq!(fixture.handler.upstream_queries.len(), 2);
+    }
+
+    #[test]
+    fn it_retransmits_then_answers_servfail() {
+        let retry_policy = RetryPolicy {
+            timeout: Duration::ZERO,
+            retries: 1,
+        };
+        let mut fixture = Fixture::new(retry_policy);
+        fixture.query(&query(7, &["www.example.net", "www.example.com"]));
+        let (forwarded, upstream_addr) = fixture.forwarded();
+
+        fixture.expire_pending();
+        assert_eq!(fixture.forwarded(), (forwarded, upstream_addr));
+        assert_eq!(fixture.handler.pending_queries.len(), 1);
+
+        fixture.expire_pending();
+        let response = fixture.response();
+        assert_eq!(response.header.id, 7);
+        assert_eq!(response.rcode(), Rcode::ServFail);
+        // the question answered from the zone is not sent on its own
+        assert!(response.answers.unwrap().is_empty());
+        assert!(fixture.handler.pending_queries.is_empty());
+        assert!(fixture.handler.upstream_queries.is_empty());
+    }
+
+    #[test]
+    fn it_forgets_questions_of_failed_queries() {
+        let retry_policy = RetryPolicy {
+            timeout: Duration::ZERO,
+            retries: 0,
+        };
+        let mut fixture = Fixture::new(retry_policy);
+        fixture.query(&query(7, &["a.example.net", "b.example.net"]));
+        let (first, first_addr) = fixture.forwarded();
+        fixture.forwarded();
+        fixture.expire_pending();
+        assert_eq!(fixture.response().rcode(), Rcode::ServFail);
+        assert!(fixture.handler.pending_queries.is_empty());
+        assert!(fixture.handler.upstream_queries.is_empty());
+
+        // the upstream sockets are closed along with the questions, so late answers
+        // aren't even read
+        let answer = answer_to(&first, vec![record("a.example.net")]);
+        fixture
+            .resolver
+            .send_to(&answer.serialize(), first_addr)
+            .unwrap();
+        let late = fixture
+            .upstream_messages
+            .recv_timeout(Duration::from_millis(300));
+        assert!(late.is_err());
+    }
+
+    #[test]
+    fn it_recognizes_the_resolver() {
+        let resolver: SocketAddr = "192.0.2.53:53".parse().unwrap();
+        assert!(is_resolver(resolver, resolver));
+        assert!(is_resolver(
+            "[::ffff:192.0.2.53]:53".parse().unwrap(),
+            resolver
+        ));
+        assert!(!is_resolver("192.0.2.53:5353".parse().unwrap(), resolver));
+        assert!(!is_resolver("192.0.2.54:53".parse().unwrap(), resolver));
+    }
+
+    #[test]
+    fn it_checks_the_question_is_echoed() {
+        let asked = query(7, &["www.example.net"]);
+        let question = &asked.questions[0];
+        let mut answer = answer_to(&asked, vec![]);
+        assert!(echoes_question(&answer, question));
+
+        answer.questions[0].name = LabelSeq::_new("WWW.Example.NET");
+        assert!(echoes_question(&answer, question));
+        answer.questions[0]._type = RecordType::Aaaa;
+        assert!(!echoes_question(&answer, question));
+        answer.questions = query(7, &["www.example.net", "www.example.net"]).questions;
+        assert!(!echoes_question(&answer, question));
+        answer.questions.clear();
+        assert!(!echoes_question(&answer, question));
+    }
 }
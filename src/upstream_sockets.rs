@@ -0,0 +1,194 @@
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::dns_edns::MAX_UDP_PAYLOAD;
+
+/// Most sockets open to the resolver at once. Each one is a file descriptor, so this stays
+/// below the usual limit of 1024 open files.
+const MAX_OPEN_SOCKETS: usize = 768;
+
+/// How long a reader blocks on its socket before checking whether the handle is gone.
+const CLOSE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A message that arrived on an upstream socket.
+pub struct UpstreamMessage {
+    pub bytes: Vec<u8>,
+    pub source: SocketAddr,
+    pub socket_id: u64,
+}
+
+/// A UDP socket opened for one question to the resolver. Once the handle is dropped, no more
+/// messages are passed on from the socket, and its reader closes it.
+pub struct UpstreamSocket {
+    id: u64,
+    socket: Arc<UdpSocket>, // shared with the reader only
+}
+
+impl UpstreamSocket {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+/// Opens the sockets questions are sent to the resolver from. Every question gets a socket
+/// on its own port picked at random by the OS, so a forged answer has to guess the port as
+/// well as the id (RFC 5452 section 9.2). Each socket has a thread blocked reading from it,
+/// which passes the messages on through a shared channel.
+pub struct UpstreamSockets {
+    resolver: SocketAddr, // resolved once, so answers can be checked without lookups
+    local_addr: SocketAddr,
+    next_id: u64,
+    open: Arc<AtomicUsize>, // sockets not closed by their reader yet
+    max_open: usize,        // MAX_OPEN_SOCKETS, lowered in tests
+    messages: mpsc::Sender<UpstreamMessage>,
+}
+
+impl UpstreamSockets {
+    /// Prepares to open sockets of the same address family as the resolver. Returns the
+    /// receiving end of the channel messages are passed on through.
+    pub fn new(resolver_addr: &str) -> io::Result<(Self, mpsc::Receiver<UpstreamMessage>)> {
+        let resolver = resolver_addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no resolver address"))?;
+        let local_addr: SocketAddr = match resolver {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let (messages, receiver) = mpsc::channel();
+        let sockets = Self {
+            resolver,
+            local_addr,
+            next_id: 0,
+            open: Arc::default(),
+            max_open: MAX_OPEN_SOCKETS,
+            messages,
+        };
+        Ok((sockets, receiver))
+    }
+
+    pub fn resolver(&self) -> SocketAddr {
+        self.resolver
+    }
+
+    /// Binds a new socket and starts its reader, unless too many are open already.
+    pub fn open(&mut self) -> io::Result<UpstreamSocket> {
+        // readers only ever lower the count, so it can't go over the limit in between
+        if self.open.load(Ordering::SeqCst) >= self.max_open {
+            return Err(io::Error::other("too many upstream sockets open"));
+        }
+        let socket = Arc::new(UdpSocket::bind(self.local_addr)?);
+        socket.set_read_timeout(Some(CLOSE_CHECK_INTERVAL))?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.open.fetch_add(1, Ordering::SeqCst);
+        let reader_socket = socket.clone();
+        let messages = self.messages.clone();
+        let open = self.open.clone();
+        thread::spawn(move || {
+            read_messages(&reader_socket, id, &messages);
+            drop(reader_socket);
+            open.fetch_sub(1, Ordering::SeqCst);
+        });
+        Ok(UpstreamSocket { id, socket })
+    }
+
+    pub fn send(&self, socket: &UpstreamSocket, bytes: &[u8]) -> io::Result<()> {
+        socket.socket.send_to(bytes, self.resolver).map(|_| ())
+    }
+}
+
+/// Passes on the messages arriving on `socket` until its handle has been dropped.
+fn read_messages(
+    socket: &Arc<UdpSocket>,
+    socket_id: u64,
+    messages: &mpsc::Sender<UpstreamMessage>,
+) {
+    let mut buf = [0; MAX_UDP_PAYLOAD as usize];
+    // the reader holds the only other reference to the socket
+    let handle_alive = || Arc::strong_count(socket) > 1;
+    while handle_alive() {
+        match socket.recv_from(&mut buf) {
+            // a message read after the handle was dropped is for a question given up on
+            Ok(_) if !handle_alive() => return,
+            Ok((size, source)) => {
+                let message = UpstreamMessage {
+                    bytes: buf[..size].to_vec(),
+                    source,
+                    socket_id,
+                };
+                if messages.send(message).is_err() {
+                    return;
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) => eprintln!("Error receiving upstream data: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn local_addr(socket: &UpstreamSocket) -> SocketAddr {
+        socket.socket.local_addr().unwrap()
+    }
+
+    #[test]
+    fn it_passes_on_messages_with_the_socket_they_arrived_on() {
+        let resolver = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let resolver_addr = resolver.local_addr().unwrap().to_string();
+        let (mut sockets, messages) = UpstreamSockets::new(&resolver_addr).unwrap();
+        let first = sockets.open().unwrap();
+        let second = sockets.open().unwrap();
+        let first_addr = local_addr(&first);
+        let second_addr = local_addr(&second);
+        assert_ne!(first_addr.port(), second_addr.port());
+
+        let to = |addr: SocketAddr| (Ipv4Addr::LOCALHOST, addr.port());
+        resolver.send_to(&[2], to(second_addr)).unwrap();
+        let message = messages.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(message.bytes, [2]);
+        assert_eq!(message.socket_id, second.id());
+        assert_eq!(message.source, resolver.local_addr().unwrap());
+
+        // nothing is passed on from a socket once its handle is dropped
+        drop(first);
+        resolver.send_to(&[1], to(first_addr)).unwrap();
+        resolver.send_to(&[3], to(second_addr)).unwrap();
+        let message = messages.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(message.bytes, [3]);
+    }
+
+    #[test]
+    fn it_limits_the_sockets_open_at_once() {
+        let (mut sockets, _messages) = UpstreamSockets::new("127.0.0.1:53").unwrap();
+        sockets.max_open = 2;
+        let first = sockets.open().unwrap();
+        let _second = sockets.open().unwrap();
+        assert!(sockets.open().is_err());
+
+        // the socket counts until its reader has closed it
+        drop(first);
+        let deadline = Instant::now() + 10 * CLOSE_CHECK_INTERVAL;
+        while sockets.open().is_err() {
+            assert!(Instant::now() < deadline, "socket was not closed");
+            thread::sleep(CLOSE_CHECK_INTERVAL / 10);
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use thiserror::Error;
+
+use crate::{
+    dns_answer::DnsAnswer,
+    dns_question::DnsQuestion,
+    dns_rcode::Rcode,
+    dns_rdata::RData,
+    dns_type::RecordType,
+    label_seq::LabelSeq,
+    zone_file::{self, ZoneFileError},
+};
+
+/// CNAMEs followed inside a zone before giving up on the chain.
+const MAX_CNAME_CHAIN: usize = 8;
+
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+pub enum ZoneError {
+    #[error(transparent)]
+    Parse(#[from] ZoneFileError),
+
+    #[error("a zone needs exactly one SOA record, found {count}")]
+    SoaCount { count: usize },
+
+    #[error("{name} is outside of zone {origin}")]
+    OutOfZone { name: String, origin: String },
+}
+
+/// A zone this server is authoritative for.
+pub struct Zone {
+    origin: LabelSeq, // owner of the SOA record
+    records: Vec<DnsAnswer>,
+}
+
+/// The response to a question about a name in a zone.
+#[derive(Debug, PartialEq)]
+pub struct ZoneAnswer {
+    pub rcode: Rcode,
+    pub authoritative: bool, // false for referrals to a delegated subzone
+    pub answers: Vec<DnsAnswer>,
+    pub authorities: Vec<DnsAnswer>,
+    pub additionals: Vec<DnsAnswer>,
+}
+
+impl Zone {
+    /// Loads a zone from master file text. The zone is named after its SOA record, and every
+    /// record must be at or below that name.
+    pub fn from_master_file(text: &str, origin: Option<&LabelSeq>) -> Result<Self, ZoneError> {
+        let records = zone_file::parse_records(text, origin)?;
+        let soas: Vec<&DnsAnswer> = records
+            .iter()
+            .filter(|record| record.rdata.record_type() == RecordType::Soa)
+            .collect();
+        let [soa] = soas[..] else {
+            return Err(ZoneError::SoaCount { count: soas.len() });
+        };
+        let origin = soa.name.clone();
+        if let Some(record) = records.iter().find(|r| !r.name.is_subdomain_of(&origin)) {
+            return Err(ZoneError::OutOfZone {
+                name: record.name.to_string(),
+                origin: origin.to_string(),
+            });
+        }
+        Ok(Self { origin, records })
+    }
+
+    pub fn origin(&self) -> &LabelSeq {
+        &self.origin
+    }
+
+    pub fn contains(&self, name: &LabelSeq) -> bool {
+        name.is_subdomain_of(&self.origin)
+    }
+
+    /// Answers `question`, whose name must be in the zone (RFC 1034 section 4.3.2, without
+    /// wildcards). CNAMEs are followed as long as they stay in the zone.
+    pub fn lookup(&self, question: &DnsQuestion) -> ZoneAnswer {
+        let mut response = ZoneAnswer {
+            rcode: Rcode::NoError,
+            authoritative: true,
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+        let mut name = question.name.clone();
+        for _ in 0..MAX_CNAME_CHAIN {
+            if let Some(cut) = self.delegation(&name) {
+                // the rest of the answer belongs to the child zone
+                response.authoritative = !response.answers.is_empty();
+                response.authorities = self.records_of_type(&cut, RecordType::Ns);
+                response.additionals = self.addresses_of_targets(&response.authorities);
+                return response;
+            }
+            let records = self.records_at(&name);
+            if records.is_empty() && !self.has_records_below(&name) {
+                response.rcode = Rcode::NXDomain;
+                response.authorities.push(self.negative_soa());
+                return response;
+            }
+            let matching: Vec<DnsAnswer> = records
+                .iter()
+                .filter(|r| {
+                    question._type == RecordType::Any || r.rdata.record_type() == question._type
+                })
+                .cloned()
+                .collect();
+            if !matching.is_empty() {
+                response.additionals = self.addresses_of_targets(&matching);
+                response.answers.extend(matching);
+                return response;
+            }
+            let Some(cname) = records
+                .into_iter()
+                .find(|r| r.rdata.record_type() == RecordType::Cname)
+            else {
+                // the name exists, but not with this type
+                response.authorities.push(self.negative_soa());
+                return response;
+            };
+            let RData::Cname(target) = &cname.rdata else {
+                unreachable!("CNAME records have CNAME data");
+            };
+            name = target.clone();
+            response.answers.push(cname);
+            if !self.contains(&name) {
+                break;
+            }
+        }
+        response
+    }
+
+    fn records_at(&self, name: &LabelSeq) -> Vec<DnsAnswer> {
+        self.records
+            .iter()
+            .filter(|r| r.name.eq_ignore_case(name))
+            .cloned()
+            .collect()
+    }
+
+    fn records_of_type(&self, name: &LabelSeq, record_type: RecordType) -> Vec<DnsAnswer> {
+        let mut records = self.records_at(name);
+        records.retain(|r| r.rdata.record_type() == record_type);
+        records
+    }
+
+    /// Names without records of their own still exist if there are records below them
+    /// (empty non-terminals), so they get NODATA instead of NXDOMAIN.
+    fn has_records_below(&self, name: &LabelSeq) -> bool {
+        self.records
+            .iter()
+            .any(|r| r.name.is_subdomain_of(name) && !r.name.eq_ignore_case(name))
+    }
+
+    /// The topmost zone cut between the origin and `name`, if the name has been delegated.
+    fn delegation(&self, name: &LabelSeq) -> Option<LabelSeq> {
+        let mut cut = None;
+        let mut current = name.clone();
+        while !current.eq_ignore_case(&self.origin) {
+            let delegated = self.records.iter().any(|r| {
+                r.name.eq_ignore_case(&current) && r.rdata.record_type() == RecordType::Ns
+            });
+            if delegated {
+                cut = Some(current.clone());
+            }
+            current = current.parent()?;
+        }
+        cut
+    }
+
+    /// A and AAAA records in the zone for the names that `records` point to, like the name
+    /// servers of a delegation (glue) or mail exchangers.
+    fn addresses_of_targets(&self, records: &[DnsAnswer]) -> Vec<DnsAnswer> {
+        let mut addresses = Vec::new();
+        for record in records {
+            let target = match &record.rdata {
+                RData::Ns(target) => target,
+                RData::Mx(mx) => &mx.exchange,
+                RData::Srv(srv) => &srv.target,
+                _ => continue,
+            };
+            addresses.extend(
+                self.records_at(target)
+                    .into_iter()
+                    .filter(|r| matches!(r.rdata, RData::A(_) | RData::Aaaa(_))),
+            );
+        }
+        addresses
+    }
+
+    /// The SOA record for negative answers, with the TTL negative answers may be cached for
+    /// (RFC 2308 section 3).
+    fn negative_soa(&self) -> DnsAnswer {
+        let mut soa = self.records_of_type(&self.origin, RecordType::Soa)[0].clone();
+        if let RData::Soa(data) = &soa.rdata {
+            soa.ttl = soa.ttl.min(data.minimum);
+        }
+        soa
+    }
+}
+
+/// The most specific zone that `name` is in, if any.
+pub fn zone_for<'a>(zones: &'a [Zone], name: &LabelSeq) -> Option<&'a Zone> {
+    zones
+        .iter()
+        .filter(|zone| zone.contains(name))
+        .max_by_key(|zone| zone.origin.labels().count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZONE: &str = "\
+$ORIGIN example.com.
+$TTL 3600
+@       SOA     ns1 hostmaster 1 7200 900 1209600 300
+        NS      ns1
+        MX      10 mail
+ns1     A       192.0.2.1
+mail    A       192.0.2.2
+        AAAA    2001:db8::2
+www     CNAME   web
+web     A       192.0.2.3
+out     CNAME   www.example.net.
+a.b.c   A       192.0.2.4
+sub     NS      ns.sub
+ns.sub  A       192.0.2.5
+";
+
+    fn zone() -> Zone {
+        Zone::from_master_file(ZONE, None).unwrap()
+    }
+
+    fn question(name: &str, _type: RecordType) -> DnsQuestion {
+        DnsQuestion {
+            name: LabelSeq::_new(name),
+            _type,
+            ..Default::default()
+        }
+    }
+
+    fn types(records: &[DnsAnswer]) -> Vec<RecordType> {
+        records.iter().map(|r| r.rdata.record_type()).collect()
+    }
+
+    #[test]
+    fn it_answers_with_additional_addresses() {
+        let answer = zone().lookup(&question("EXAMPLE.com", RecordType::Mx));
+        assert!(answer.authoritative);
+        assert_eq!(answer.rcode, Rcode::NoError);
+        assert_eq!(types(&answer.answers), [RecordType::Mx]);
+        assert!(answer.authorities.is_empty());
+        assert_eq!(
+            types(&answer.additionals),
+            [RecordType::A, RecordType::Aaaa]
+        );
+    }
+
+    #[test]
+    fn it_follows_cnames() {
+        let answer = zone().lookup(&question("www.example.com", RecordType::A));
+        assert_eq!(types(&answer.answers), [RecordType::Cname, RecordType::A]);
+        assert_eq!(answer.answers[1].name, LabelSeq::_new("web.example.com"));
+
+        let answer = zone().lookup(&question("out.example.com", RecordType::A));
+        assert_eq!(types(&answer.answers), [RecordType::Cname]);
+        assert_eq!(answer.rcode, Rcode::NoError);
+        assert!(answer.authorities.is_empty());
+    }
+
+    #[test]
+    fn it_refers_to_delegated_subzones_with_glue() {
+        let answer = zone().lookup(&question("www.sub.example.com", RecordType::A));
+        assert!(!answer.authoritative);
+        assert_eq!(answer.rcode, Rcode::NoError);
+        assert!(answer.answers.is_empty());
+        assert_eq!(types(&answer.authorities), [RecordType::Ns]);
+        assert_eq!(
+            answer.authorities[0].name,
+            LabelSeq::_new("sub.example.com")
+        );
+        assert_eq!(
+            answer.additionals[0].name,
+            LabelSeq::_new("ns.sub.example.com")
+        );
+    }
+
+    #[test]
+    fn it_answers_nxdomain_and_nodata_with_soa() {
+        let answer = zone().lookup(&question("nope.example.com", RecordType::A));
+        assert!(answer.authoritative);
+        assert_eq!(answer.rcode, Rcode::NXDomain);
+        assert_eq!(types(&answer.authorities), [RecordType::Soa]);
+        assert_eq!(answer.authorities[0].ttl, 300);
+
+        for name in ["web.example.com", "b.c.example.com"] {
+            let answer = zone().lookup(&question(name, RecordType::Aaaa));
+            assert_eq!(answer.rcode, Rcode::NoError);
+            assert!(answer.answers.is_empty());
+            assert_eq!(types(&answer.authorities), [RecordType::Soa]);
+        }
+    }
+
+    #[test]
+    fn it_rejects_bad_zones() {
+        assert_eq!(
+            Zone::from_master_file("example.com. 60 A 192.0.2.1", None).err(),
+            Some(ZoneError::SoaCount { count: 0 })
+        );
+        let outside = format!("{}www.example.net. A 192.0.2.1\n", ZONE);
+        assert_eq!(
+            Zone::from_master_file(&outside, None).err(),
+            Some(ZoneError::OutOfZone {
+                name: "www.example.net.".into(),
+                origin: "example.com.".into()
+            })
+        );
+    }
+
+    #[test]
+    fn it_picks_the_most_specific_zone() {
+        let child = "$ORIGIN sub.example.com.\n@ 60 SOA ns hostmaster 1 2 3 4 5\n";
+        let zones = [zone(), Zone::from_master_file(child, None).unwrap()];
+        let name = LabelSeq::_new("www.sub.example.com");
+        assert_eq!(zone_for(&zones, &name).unwrap().origin(), &zones[1].origin);
+        let name = LabelSeq::_new("www.example.net");
+        assert!(zone_for(&zones, &name).is_none());
+    }
+}
@@ -0,0 +1,1006 @@
+use std::{
+    fmt,
+    iter::Peekable,
+    mem,
+    str::{Chars, FromStr},
+};
+
+use thiserror::Error;
+
+use crate::{
+    dns_answer::DnsAnswer,
+    dns_rdata::{Mx, RData, Soa, Srv, Txt},
+    dns_serde::DnsSerialize,
+    dns_type::RecordType,
+    label_seq::LabelSeq,
+};
+
+/// Where in the text a problem was found. Lines and columns start at 1, and columns count
+/// characters.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Errors produced while parsing a master file. Every variant carries the position where the
+/// problem was detected.
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+pub enum ZoneFileError {
+    #[error("{at}: unbalanced parentheses")]
+    UnbalancedParentheses { at: Position },
+
+    #[error("{at}: unterminated quoted string")]
+    UnterminatedString { at: Position },
+
+    #[error("{at}: entry ends too early")]
+    UnexpectedEnd { at: Position },
+
+    #[error("{at}: unexpected {token:?} after the record data")]
+    TrailingData { at: Position, token: String },
+
+    #[error("{at}: unsupported directive {token}")]
+    UnknownDirective { at: Position, token: String },
+
+    #[error("{at}: no previous owner name to repeat")]
+    MissingOwner { at: Position },
+
+    #[error("{at}: relative name {token:?} but no origin is set")]
+    MissingOrigin { at: Position, token: String },
+
+    #[error("{at}: no TTL given, and no $TTL or previous TTL to use instead")]
+    MissingTtl { at: Position },
+
+    #[error("{at}: invalid name {token:?}")]
+    BadName { at: Position, token: String },
+
+    #[error("{at}: unknown or unsupported record type {token:?}")]
+    BadType { at: Position, token: String },
+
+    #[error("{at}: invalid number {token:?}")]
+    BadNumber { at: Position, token: String },
+
+    #[error("{at}: invalid address {token:?}")]
+    BadAddress { at: Position, token: String },
+
+    #[error("{at}: invalid escape in {token:?}")]
+    BadEscape { at: Position, token: String },
+
+    #[error("{at}: character-string longer than 255 bytes")]
+    StringTooLong { at: Position },
+
+    #[error("{at}: record data longer than 65535 bytes")]
+    RdataTooLong { at: Position },
+
+    #[error("{at}: invalid \\# data for type {record_type}")]
+    BadGenericData {
+        at: Position,
+        record_type: RecordType,
+    },
+}
+
+/// A field of an entry, with where it starts and ends. Escapes are kept, including in quoted
+/// strings, and only decoded once it is known whether the field is a name or a string.
+struct Token {
+    text: String,
+    quoted: bool,
+    at: Position,
+    end: Position, // just after the field
+}
+
+/// The fields of one entry. Entries end at the end of the line, unless inside parentheses.
+struct Entry {
+    blank_owner: bool, // the line starts with whitespace, so the previous owner is repeated
+    tokens: Vec<Token>,
+}
+
+/// The fields of an entry not consumed yet.
+struct Fields<'a> {
+    tokens: &'a [Token],
+    end: Position, // end of the last field consumed, for errors about missing fields
+}
+
+impl<'a> Fields<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.first()
+    }
+
+    fn next(&mut self) -> Result<&'a Token, ZoneFileError> {
+        let (token, rest) = self
+            .tokens
+            .split_first()
+            .ok_or(ZoneFileError::UnexpectedEnd { at: self.end })?;
+        self.tokens = rest;
+        self.end = token.end;
+        Ok(token)
+    }
+
+    fn rest(&mut self) -> &'a [Token] {
+        mem::take(&mut self.tokens)
+    }
+}
+
+/// Master file text, read a character at a time while keeping track of the position.
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize, // of the last character read, 0 at the start of a line
+}
+
+impl Cursor<'_> {
+    fn next_if(&mut self, accept: impl FnOnce(&char) -> bool) -> Option<char> {
+        let c = self.chars.next_if(accept)?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 0;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        self.next_if(|_| true)
+    }
+
+    /// Position of the last character read.
+    fn position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Position just after the last character read.
+    fn end(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column + 1,
+        }
+    }
+}
+
+/// Parses the records of a master file (RFC 1035 section 5.1), with the `$TTL` directive of
+/// RFC 2308 and the `\#` generic data of RFC 3597. Relative names are relative to `origin`
+/// until a `$ORIGIN` directive changes it. `$INCLUDE` is not supported.
+pub fn parse_records(
+    text: &str,
+    origin: Option<&LabelSeq>,
+) -> Result<Vec<DnsAnswer>, ZoneFileError> {
+    let mut origin = origin.cloned();
+    let mut default_ttl = None; // from $TTL
+    let mut last_ttl = None;
+    let mut last_owner = None;
+    let mut last_class = 1;
+    let mut records = Vec::new();
+    for entry in tokenize(text)? {
+        let first = &entry.tokens[0];
+        let mut fields = Fields {
+            tokens: &entry.tokens,
+            end: first.at,
+        };
+        if !entry.blank_owner && !first.quoted && first.text.starts_with('$') {
+            fields.next()?;
+            match first.text.to_ascii_uppercase().as_str() {
+                "$ORIGIN" => origin = Some(parse_name(fields.next()?, origin.as_ref())?),
+                "$TTL" => default_ttl = Some(parse_ttl(fields.next()?)?),
+                _ => {
+                    return Err(ZoneFileError::UnknownDirective {
+                        at: first.at,
+                        token: first.text.clone(),
+                    })
+                }
+            }
+            expect_end(&fields)?;
+            continue;
+        }
+
+        let name = if entry.blank_owner {
+            last_owner
+                .clone()
+                .ok_or(ZoneFileError::MissingOwner { at: first.at })?
+        } else {
+            parse_name(fields.next()?, origin.as_ref())?
+        };
+        // TTL and class are both optional and may come in either order
+        let mut ttl = None;
+        let mut class = None;
+        while let Some(token) = fields.peek() {
+            if ttl.is_none() && starts_with_digit(&token.text) {
+                ttl = Some(parse_ttl(token)?);
+            } else if let (None, Some(code)) = (class, parse_class(&token.text)) {
+                class = Some(code);
+            } else {
+                break;
+            }
+            fields.next()?;
+        }
+        let type_token = fields.next()?;
+        let record_type = RecordType::from_mnemonic(&type_token.text)
+            .filter(|t| !matches!(t, RecordType::Opt | RecordType::Any))
+            .ok_or_else(|| ZoneFileError::BadType {
+                at: type_token.at,
+                token: type_token.text.clone(),
+            })?;
+        let ttl = match ttl {
+            Some(ttl) => {
+                last_ttl = Some(ttl);
+                ttl
+            }
+            None => default_ttl
+                .or(last_ttl)
+                .ok_or(ZoneFileError::MissingTtl { at: type_token.at })?,
+        };
+        let rdata = parse_rdata(record_type, &mut fields, origin.as_ref())?;
+        expect_end(&fields)?;
+
+        last_owner = Some(name.clone());
+        last_class = class.unwrap_or(last_class);
+        records.push(DnsAnswer {
+            name,
+            rdata,
+            _class: last_class,
+            ttl,
+        });
+    }
+    Ok(records)
+}
+
+/// Writes `records` as master file text, one per line with an absolute owner name and an
+/// explicit TTL and class, so that it parses back to the same records without an origin.
+/// OPT pseudo-records have no master file form and are left out.
+#[allow(dead_code)] // nothing writes zones out yet
+pub fn format_records(records: &[DnsAnswer]) -> String {
+    records
+        .iter()
+        .filter(|record| !matches!(record.rdata, RData::Opt(_)))
+        .map(|record| format!("{}\n", record))
+        .collect()
+}
+
+/// Splits `text` into entries, dropping comments and parentheses.
+fn tokenize(text: &str) -> Result<Vec<Entry>, ZoneFileError> {
+    let mut entries = Vec::new();
+    let mut entry = Entry {
+        blank_owner: false,
+        tokens: Vec::new(),
+    };
+    let mut open_parentheses = 0;
+    let mut outermost_parenthesis = None;
+    let mut cursor = Cursor {
+        chars: text.chars().peekable(),
+        line: 1,
+        column: 0,
+    };
+    while let Some(c) = cursor.next() {
+        let at = cursor.position();
+        match c {
+            '\n' => {
+                if open_parentheses == 0 {
+                    let next = Entry {
+                        blank_owner: false,
+                        tokens: Vec::new(),
+                    };
+                    let done = mem::replace(&mut entry, next);
+                    if !done.tokens.is_empty() {
+                        entries.push(done);
+                    }
+                }
+            }
+            ';' => while cursor.next_if(|&c| c != '\n').is_some() {},
+            '(' => {
+                if open_parentheses == 0 {
+                    outermost_parenthesis = Some(at);
+                }
+                open_parentheses += 1;
+            }
+            ')' => {
+                if open_parentheses == 0 {
+                    return Err(ZoneFileError::UnbalancedParentheses { at });
+                }
+                open_parentheses -= 1;
+            }
+            c if c.is_whitespace() => {
+                if at.column == 1 && open_parentheses == 0 {
+                    entry.blank_owner = true;
+                }
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match cursor.next_if(|&c| c != '\n') {
+                        None => return Err(ZoneFileError::UnterminatedString { at }),
+                        Some('"') => break,
+                        Some('\\') => {
+                            text.push('\\');
+                            text.extend(cursor.next_if(|&c| c != '\n'));
+                        }
+                        Some(c) => text.push(c),
+                    }
+                }
+                entry.tokens.push(Token {
+                    text,
+                    quoted: true,
+                    at,
+                    end: cursor.end(),
+                });
+            }
+            c => {
+                let mut text = String::new();
+                let mut next = Some(c);
+                while let Some(c) = next {
+                    text.push(c);
+                    if c == '\\' {
+                        text.extend(cursor.next_if(|&c| c != '\n'));
+                    }
+                    next = cursor
+                        .next_if(|&c| !c.is_whitespace() && !matches!(c, ';' | '(' | ')' | '"'));
+                }
+                entry.tokens.push(Token {
+                    text,
+                    quoted: false,
+                    at,
+                    end: cursor.end(),
+                });
+            }
+        }
+    }
+    if let Some(at) = outermost_parenthesis.filter(|_| open_parentheses > 0) {
+        return Err(ZoneFileError::UnbalancedParentheses { at });
+    }
+    if !entry.tokens.is_empty() {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn expect_end(fields: &Fields) -> Result<(), ZoneFileError> {
+    match fields.peek() {
+        Some(token) => Err(ZoneFileError::TrailingData {
+            at: token.at,
+            token: token.text.clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn parse_rdata(
+    record_type: RecordType,
+    fields: &mut Fields,
+    origin: Option<&LabelSeq>,
+) -> Result<RData, ZoneFileError> {
+    let rdata_at = fields.peek().map_or(fields.end, |token| token.at);
+    if matches!(fields.peek(), Some(token) if !token.quoted && token.text == "\\#") {
+        fields.next()?;
+        return parse_generic_rdata(record_type, fields, rdata_at);
+    }
+    let rdata = match record_type {
+        RecordType::A => RData::A(parse_address(fields.next()?)?),
+        RecordType::Aaaa => RData::Aaaa(parse_address(fields.next()?)?),
+        RecordType::Ns => RData::Ns(parse_name(fields.next()?, origin)?),
+        RecordType::Cname => RData::Cname(parse_name(fields.next()?, origin)?),
+        RecordType::Ptr => RData::Ptr(parse_name(fields.next()?, origin)?),
+        RecordType::Soa => RData::Soa(Soa {
+            mname: parse_name(fields.next()?, origin)?,
+            rname: parse_name(fields.next()?, origin)?,
+            serial: parse_number(fields.next()?)?,
+            refresh: parse_ttl(fields.next()?)?,
+            retry: parse_ttl(fields.next()?)?,
+            expire: parse_ttl(fields.next()?)?,
+            minimum: parse_ttl(fields.next()?)?,
+        }),
+        RecordType::Mx => RData::Mx(Mx {
+            preference: parse_number(fields.next()?)?,
+            exchange: parse_name(fields.next()?, origin)?,
+        }),
+        RecordType::Txt => {
+            let mut strings = vec![parse_character_string(fields.next()?)?];
+            for token in fields.rest() {
+                strings.push(parse_character_string(token)?);
+            }
+            RData::Txt(Txt { strings })
+        }
+        RecordType::Srv => RData::Srv(Srv {
+            priority: parse_number(fields.next()?)?,
+            weight: parse_number(fields.next()?)?,
+            port: parse_number(fields.next()?)?,
+            target: parse_name(fields.next()?, origin)?,
+        }),
+        // types this crate does not model only have the generic form
+        _ => {
+            return Err(ZoneFileError::BadGenericData {
+                at: rdata_at,
+                record_type,
+            })
+        }
+    };
+    // RDLENGTH is 2 bytes, and only TXT data can get that long
+    if rdata.serialize().len() > u16::MAX as usize {
+        return Err(ZoneFileError::RdataTooLong { at: rdata_at });
+    }
+    Ok(rdata)
+}
+
+/// RFC 3597 section 5: `\# <length> <hex>`, where the hex may be split into several fields.
+/// Known types are decoded like RDATA off the wire.
+fn parse_generic_rdata(
+    record_type: RecordType,
+    fields: &mut Fields,
+    at: Position,
+) -> Result<RData, ZoneFileError> {
+    let length: u16 = parse_number(fields.next()?)?;
+    let bad_data = ZoneFileError::BadGenericData { at, record_type };
+    let hex: String = fields
+        .rest()
+        .iter()
+        .map(|token| token.text.as_str())
+        .collect();
+    let mut wire = length.to_be_bytes().to_vec();
+    for pair in hex.as_bytes().chunks(2) {
+        if pair.len() != 2 || !pair.iter().all(u8::is_ascii_hexdigit) {
+            return Err(bad_data);
+        }
+        let pair = std::str::from_utf8(pair).expect("hex digits are ASCII");
+        wire.push(u8::from_str_radix(pair, 16).expect("two hex digits fit in a byte"));
+    }
+    if wire.len() != length as usize + 2 {
+        return Err(bad_data);
+    }
+    match RData::deserialize(&wire, record_type, &wire) {
+        Ok((_, rdata)) => Ok(rdata),
+        Err(_) => Err(bad_data),
+    }
+}
+
+/// `@` is the origin, names ending with a dot are absolute, and other names are relative to
+/// the origin.
+fn parse_name(token: &Token, origin: Option<&LabelSeq>) -> Result<LabelSeq, ZoneFileError> {
+    let bad_name = || ZoneFileError::BadName {
+        at: token.at,
+        token: token.text.clone(),
+    };
+    let missing_origin = || ZoneFileError::MissingOrigin {
+        at: token.at,
+        token: token.text.clone(),
+    };
+    if token.text == "@" {
+        return origin.cloned().ok_or_else(missing_origin);
+    }
+    if token.text == "." {
+        return Ok(LabelSeq::default());
+    }
+    // split on dots that are not escaped
+    let mut pieces = vec![String::new()];
+    let mut chars = token.text.chars();
+    while let Some(c) = chars.next() {
+        let piece = pieces.last_mut().expect("there is always a piece");
+        match c {
+            '.' => pieces.push(String::new()),
+            '\\' => {
+                piece.push(c);
+                piece.extend(chars.next());
+            }
+            c => piece.push(c),
+        }
+    }
+    let absolute = pieces.len() > 1 && pieces.last().is_some_and(String::is_empty);
+    if absolute {
+        pieces.pop();
+    }
+    let mut labels = Vec::new();
+    for piece in pieces {
+        let bytes = unescape(&piece, token)?;
+        labels.push(String::from_utf8(bytes).map_err(|_| bad_name())?);
+    }
+    if !absolute {
+        labels.extend(
+            origin
+                .ok_or_else(missing_origin)?
+                .labels()
+                .map(String::from),
+        );
+    }
+    LabelSeq::from_labels(&labels).ok_or_else(bad_name)
+}
+
+/// Decodes `\X` (the character X) and `\DDD` (the byte with decimal value DDD) escapes.
+fn unescape(text: &str, token: &Token) -> Result<Vec<u8>, ZoneFileError> {
+    let bad_escape = || ZoneFileError::BadEscape {
+        at: token.at,
+        token: token.text.clone(),
+    };
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => chars.next().ok_or_else(bad_escape)?,
+            c => {
+                bytes.extend(c.to_string().as_bytes());
+                continue;
+            }
+        };
+        if c.is_ascii_digit() {
+            let digits: String = [Some(c), chars.next(), chars.next()]
+                .into_iter()
+                .flatten()
+                .collect();
+            if digits.len() != 3 || !digits.chars().all(|c| c.is_ascii_digit()) {
+                return Err(bad_escape());
+            }
+            bytes.push(digits.parse().map_err(|_| bad_escape())?);
+        } else {
+            bytes.extend(c.to_string().as_bytes());
+        }
+    }
+    Ok(bytes)
+}
+
+fn parse_character_string(token: &Token) -> Result<Vec<u8>, ZoneFileError> {
+    let bytes = unescape(&token.text, token)?;
+    if bytes.len() > 255 {
+        return Err(ZoneFileError::StringTooLong { at: token.at });
+    }
+    Ok(bytes)
+}
+
+fn starts_with_digit(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_digit())
+}
+
+fn parse_number<T: FromStr>(token: &Token) -> Result<T, ZoneFileError> {
+    let bad_number = || ZoneFileError::BadNumber {
+        at: token.at,
+        token: token.text.clone(),
+    };
+    if !starts_with_digit(&token.text) {
+        return Err(bad_number());
+    }
+    token.text.parse().map_err(|_| bad_number())
+}
+
+/// A number of seconds, or a duration with units like `1h30m` as accepted by BIND.
+fn parse_ttl(token: &Token) -> Result<u32, ZoneFileError> {
+    if let Ok(ttl) = parse_number(token) {
+        return Ok(ttl);
+    }
+    let bad_ttl = || ZoneFileError::BadNumber {
+        at: token.at,
+        token: token.text.clone(),
+    };
+    let mut ttl: u32 = 0;
+    let mut digits = String::new();
+    for c in token.text.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(bad_ttl()),
+        };
+        let value: u32 = digits.parse().map_err(|_| bad_ttl())?;
+        ttl = value
+            .checked_mul(unit)
+            .and_then(|seconds| ttl.checked_add(seconds))
+            .ok_or_else(bad_ttl)?;
+        digits.clear();
+    }
+    if !digits.is_empty() || !starts_with_digit(&token.text) {
+        return Err(bad_ttl());
+    }
+    Ok(ttl)
+}
+
+/// `IN`, or `CLASS<code>` for other classes (RFC 3597).
+fn parse_class(text: &str) -> Option<u16> {
+    if text.eq_ignore_ascii_case("IN") {
+        return Some(1);
+    }
+    let code = text
+        .get(..5)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("CLASS"))
+        .map(|_| &text[5..])?;
+    if !starts_with_digit(code) {
+        return None;
+    }
+    code.parse().ok()
+}
+
+fn parse_address<T: FromStr>(token: &Token) -> Result<T, ZoneFileError> {
+    token.text.parse().map_err(|_| ZoneFileError::BadAddress {
+        at: token.at,
+        token: token.text.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn record(name: &str, ttl: u32, rdata: RData) -> DnsAnswer {
+        DnsAnswer {
+            name: LabelSeq::_new(name),
+            rdata,
+            ttl,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_parses_a_zone() {
+        let text = "\
+$ORIGIN example.com.
+$TTL 1h
+@   IN  SOA ns1 hostmaster (
+            2024010101 ; serial
+            2h 15m 2w 300 )
+    IN  NS  ns1
+    NS  ns1.example.net.
+ns1 600 A   192.0.2.1
+    IN 60 AAAA 2001:db8::1 ; repeats the owner
+www CNAME   @
+@   MX  10 mail
+_sip._tcp SRV 0 5 5060 sip
+";
+        let records = parse_records(text, None).unwrap();
+        let ns1 = LabelSeq::_new("ns1.example.com");
+        assert_eq!(
+            records,
+            [
+                record(
+                    "example.com",
+                    3600,
+                    RData::Soa(Soa {
+                        mname: ns1.clone(),
+                        rname: LabelSeq::_new("hostmaster.example.com"),
+                        serial: 2024010101,
+                        refresh: 7200,
+                        retry: 900,
+                        expire: 1209600,
+                        minimum: 300,
+                    })
+                ),
+                record("example.com", 3600, RData::Ns(ns1.clone())),
+                record(
+                    "example.com",
+                    3600,
+                    RData::Ns(LabelSeq::_new("ns1.example.net"))
+                ),
+                record(
+                    "ns1.example.com",
+                    600,
+                    RData::A(Ipv4Addr::new(192, 0, 2, 1))
+                ),
+                record(
+                    "ns1.example.com",
+                    60,
+                    RData::Aaaa("2001:db8::1".parse().unwrap())
+                ),
+                record(
+                    "www.example.com",
+                    3600,
+                    RData::Cname(LabelSeq::_new("example.com"))
+                ),
+                record(
+                    "example.com",
+                    3600,
+                    RData::Mx(Mx {
+                        preference: 10,
+                        exchange: LabelSeq::_new("mail.example.com"),
+                    })
+                ),
+                record(
+                    "_sip._tcp.example.com",
+                    3600,
+                    RData::Srv(Srv {
+                        priority: 0,
+                        weight: 5,
+                        port: 5060,
+                        target: LabelSeq::_new("sip.example.com"),
+                    })
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_uses_the_previous_ttl_without_ttl_directive() {
+        let text = "a.example. 300 IN A 192.0.2.1\nb.example. IN A 192.0.2.2\n";
+        let records = parse_records(text, None).unwrap();
+        assert_eq!(records[1].ttl, 300);
+        assert_eq!(
+            parse_records("a.example. IN A 192.0.2.1", None),
+            Err(ZoneFileError::MissingTtl {
+                at: Position {
+                    line: 1,
+                    column: 15
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn it_parses_txt_strings() {
+        let text = "txt 60 TXT \"v=spf1 -all\" plain \"semi;colon\" \"say \\\"hi\\\"\" \\007";
+        let records = parse_records(text, Some(&LabelSeq::_new("example.com"))).unwrap();
+        assert_eq!(
+            records[0].rdata,
+            RData::Txt(Txt {
+                strings: vec![
+                    b"v=spf1 -all".to_vec(),
+                    b"plain".to_vec(),
+                    b"semi;colon".to_vec(),
+                    b"say \"hi\"".to_vec(),
+                    vec![7],
+                ]
+            })
+        );
+        let long = format!("t 60 TXT \"{}\"", "x".repeat(256));
+        assert_eq!(
+            parse_records(&long, Some(&LabelSeq::_new("example.com"))),
+            Err(ZoneFileError::StringTooLong {
+                at: Position {
+                    line: 1,
+                    column: 10
+                }
+            })
+        );
+        // 256 strings of 255 bytes take 65536 bytes on the wire
+        let string = format!(" \"{}\"", "x".repeat(255));
+        let too_many = format!("t 60 TXT{}", string.repeat(256));
+        assert_eq!(
+            parse_records(&too_many, Some(&LabelSeq::_new("example.com"))),
+            Err(ZoneFileError::RdataTooLong {
+                at: Position {
+                    line: 1,
+                    column: 10
+                }
+            })
+        );
+        let most = format!("t 60 TXT{}", string.repeat(255));
+        assert!(parse_records(&most, Some(&LabelSeq::_new("example.com"))).is_ok());
+    }
+
+    #[test]
+    fn it_parses_generic_data() {
+        let text = "\
+a.example. 60 CLASS1 TYPE1 \\# 4 C0000201
+b.example. 60 TYPE65280 \\# 3 0a 0b0c
+c.example. 60 TYPE65280 \\# 0
+";
+        let records = parse_records(text, None).unwrap();
+        assert_eq!(records[0].rdata, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!(records[1].rdata, RData::Unknown(65280, vec![10, 11, 12]));
+        assert_eq!(records[2].rdata, RData::Unknown(65280, vec![]));
+        assert_eq!(
+            parse_records("a.example. 60 A \\# 3 C00002", None),
+            Err(ZoneFileError::BadGenericData {
+                at: Position {
+                    line: 1,
+                    column: 17
+                },
+                record_type: RecordType::A
+            })
+        );
+        assert_eq!(
+            parse_records("a.example. 60 TYPE99 0a", None),
+            Err(ZoneFileError::BadGenericData {
+                at: Position {
+                    line: 1,
+                    column: 22
+                },
+                record_type: RecordType::Unknown(99)
+            })
+        );
+    }
+
+    #[test]
+    fn it_reports_where_errors_are() {
+        let origin = LabelSeq::_new("example.com");
+        let parse = |text: &str| parse_records(text, Some(&origin));
+        let at = |line, column| Position { line, column };
+        assert_eq!(
+            parse("$TTL 60\n@ SOA ns1 host ( 1 2 3 4 5\n"),
+            Err(ZoneFileError::UnbalancedParentheses { at: at(2, 16) })
+        );
+        assert_eq!(
+            parse("a 60 TXT \"open\nb 60 A 192.0.2.1"),
+            Err(ZoneFileError::UnterminatedString { at: at(1, 10) })
+        );
+        assert_eq!(
+            parse("$TTL 60\n\na BOGUS 1\n"),
+            Err(ZoneFileError::BadType {
+                at: at(3, 3),
+                token: "BOGUS".into()
+            })
+        );
+        assert_eq!(
+            parse("@ 60 SOA ns1 host (\n  1 2h x 4 5 )"),
+            Err(ZoneFileError::BadNumber {
+                at: at(2, 8),
+                token: "x".into()
+            })
+        );
+        assert_eq!(
+            parse("a 60 A 192.0.2.1 (\n extra )"),
+            Err(ZoneFileError::TrailingData {
+                at: at(2, 2),
+                token: "extra".into()
+            })
+        );
+        assert_eq!(
+            parse("a 60 MX 10\n"),
+            Err(ZoneFileError::UnexpectedEnd { at: at(1, 11) })
+        );
+        assert_eq!(
+            parse("a 60 A 192.0.2\n"),
+            Err(ZoneFileError::BadAddress {
+                at: at(1, 8),
+                token: "192.0.2".into()
+            })
+        );
+        assert_eq!(
+            parse("$INCLUDE other.zone"),
+            Err(ZoneFileError::UnknownDirective {
+                at: at(1, 1),
+                token: "$INCLUDE".into()
+            })
+        );
+        assert_eq!(
+            parse("  60 A 192.0.2.1"),
+            Err(ZoneFileError::MissingOwner { at: at(1, 3) })
+        );
+        assert_eq!(
+            parse_records("a 60 A 192.0.2.1", None),
+            Err(ZoneFileError::MissingOrigin {
+                at: at(1, 1),
+                token: "a".into()
+            })
+        );
+        assert_eq!(
+            parse("a..b 60 A 192.0.2.1"),
+            Err(ZoneFileError::BadName {
+                at: at(1, 1),
+                token: "a..b".into()
+            })
+        );
+        assert_eq!(
+            parse("a 60 TXT \\25x").unwrap_err().to_string(),
+            "line 1, column 10: invalid escape in \"\\\\25x\""
+        );
+    }
+
+    #[test]
+    fn it_changes_origin() {
+        let text = "\
+$ORIGIN example.com.
+$TTL 60
+www A 192.0.2.1
+$ORIGIN sub
+www A 192.0.2.2
+";
+        let records = parse_records(text, None).unwrap();
+        assert_eq!(records[0].name, LabelSeq::_new("www.example.com"));
+        assert_eq!(records[1].name, LabelSeq::_new("www.sub.example.com"));
+    }
+
+    #[test]
+    fn it_round_trips_records_through_text() {
+        let records = [
+            record("example.com", 60, RData::A(Ipv4Addr::new(192, 0, 2, 1))),
+            record(
+                "example.com",
+                60,
+                RData::Aaaa("2001:db8::1".parse().unwrap()),
+            ),
+            record("", 0, RData::Ns(LabelSeq::_new("a b;c.@.é.example"))),
+            record("w\\(x).example", 1, RData::Cname(LabelSeq::default())),
+            record(
+                "example.com",
+                3600,
+                RData::Soa(Soa {
+                    mname: LabelSeq::_new("ns1.example.com"),
+                    rname: LabelSeq::_new("host$master.example.com"),
+                    serial: u32::MAX,
+                    refresh: 7200,
+                    retry: 900,
+                    expire: 1209600,
+                    minimum: 300,
+                }),
+            ),
+            record(
+                "1.2.0.192.in-addr.arpa",
+                60,
+                RData::Ptr(LabelSeq::_new("example.com")),
+            ),
+            record(
+                "example.com",
+                60,
+                RData::Mx(Mx {
+                    preference: 10,
+                    exchange: LabelSeq::_new("mail.example.com"),
+                }),
+            ),
+            record(
+                "example.com",
+                60,
+                RData::Txt(Txt {
+                    strings: vec![(0..=254).collect(), vec![255], Vec::new()],
+                }),
+            ),
+            record(
+                "_sip._tcp.example.com",
+                60,
+                RData::Srv(Srv {
+                    priority: 1,
+                    weight: 2,
+                    port: 5060,
+                    target: LabelSeq::_new("sip.example.com"),
+                }),
+            ),
+            record("example.com", 60, RData::Unknown(65280, vec![0, 0xff])),
+            record("example.com", 60, RData::Unknown(65280, Vec::new())),
+            DnsAnswer {
+                _class: 3,
+                ..record("example.com", 60, RData::A(Ipv4Addr::LOCALHOST))
+            },
+        ];
+        let text = format_records(&records);
+        assert_eq!(text.lines().count(), records.len());
+        assert_eq!(parse_records(&text, None).unwrap(), records);
+
+        let opt = record("example.com", 0, RData::Opt(Vec::new()));
+        let with_opt = [&records[..], &[opt]].concat();
+        assert_eq!(format_records(&with_opt), text);
+
+        // strings too long for one character-string come back split, as on the wire
+        let long = record(
+            "example.com",
+            60,
+            RData::Txt(Txt {
+                strings: vec![vec![b'a'; 300]],
+            }),
+        );
+        let text = format_records(std::slice::from_ref(&long));
+        let reparsed = parse_records(&text, None).unwrap();
+        let RData::Txt(txt) = &reparsed[0].rdata else {
+            panic!("expected TXT, got {:?}", reparsed[0].rdata);
+        };
+        assert_eq!(txt.strings, [vec![b'a'; 255], vec![b'a'; 45]]);
+        assert_eq!(reparsed[0].serialize(), long.serialize());
+    }
+
+    #[test]
+    fn it_formats_canonical_text() {
+        let text = "\
+$ORIGIN example.com.
+$TTL 1h
+@   SOA ns1 hostmaster ( 1 2h 15m 2w 300 ) ; comment
+    MX  10 mail
+www 60 CLASS3 TXT hi \"there\"
+";
+        let canonical = format_records(&parse_records(text, None).unwrap());
+        assert_eq!(
+            canonical,
+            "\
+example.com. 3600 IN SOA ns1.example.com. hostmaster.example.com. 1 7200 900 1209600 300
+example.com. 3600 IN MX 10 mail.example.com.
+www.example.com. 60 CLASS3 TXT \"hi\" \"there\"
+"
+        );
+        let reparsed = parse_records(&canonical, None).unwrap();
+        assert_eq!(format_records(&reparsed), canonical);
+    }
+}